@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+use super::{open_database_at, read_blob};
+use super::{Error, Result};
+
+//{{{ Asset
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    Image,
+    Font,
+    SoundEffect,
+    Music,
+    Card,
+}
+impl AssetKind {
+    // Name of the table with these assets in the database
+    pub fn table(&self) -> &'static str {
+        match *self {
+            AssetKind::Image => "images",
+            AssetKind::Font => "fonts",
+            AssetKind::SoundEffect => "sound_effects",
+            AssetKind::Music => "musics",
+            AssetKind::Card => "cards",
+        }
+    }
+
+    // Name of the blob column of the table
+    fn column(&self) -> &'static str {
+        match *self {
+            AssetKind::Image => "image",
+            AssetKind::Font => "font",
+            AssetKind::SoundEffect => "effect",
+            AssetKind::Music => "music",
+            AssetKind::Card => "tiles",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Asset {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    // Cards only: the card is used as drawn, without rotations and flips
+    pub fixed: bool,
+}
+//}}}
+
+//{{{ AssetSource
+/*
+ * Where images, fonts, sounds and cards of the game come from.
+ * Assets are returned in a stable order, so seeded runs stay the same.
+ */
+pub trait AssetSource {
+    fn load(&self, kind: AssetKind) -> Result<Vec<Asset>>;
+
+    fn load_first(&self, kind: AssetKind) -> Result<Asset> {
+        match self.load(kind)?.into_iter().next() {
+            Some(asset) => Ok(asset),
+            None => Err(Error::missing_row(kind.table(), 1)),
+        }
+    }
+}
+//}}}
+
+//{{{ SqliteAssets
+/*
+ * Assets packed into the tables of the game database
+ */
+pub struct SqliteAssets {
+    db_conn: Connection,
+}
+impl SqliteAssets {
+    pub fn open(db_path: &Path) -> Result<SqliteAssets> {
+        Ok(SqliteAssets {
+            db_conn: open_database_at(db_path)?,
+        })
+    }
+}
+impl AssetSource for SqliteAssets {
+    fn load(&self, kind: AssetKind) -> Result<Vec<Asset>> {
+        // Databases made before the `name` column of cards
+        // use rowids instead
+        let table = kind.table();
+        let name_column = match kind {
+            AssetKind::Card if !has_column(&self.db_conn, table, "name")? => {
+                "cast(rowid as text)"
+            }
+            _ => "name",
+        };
+        // Databases made before the `fixed` column have only free cards
+        let fixed_column = match kind {
+            AssetKind::Card if has_column(&self.db_conn, table, "fixed")? => {
+                "fixed"
+            }
+            _ => "0",
+        };
+        let query = format!(
+            "select rowid, {}, {} from {} order by rowid;",
+            name_column,
+            fixed_column,
+            kind.table()
+        );
+        let mut statement = self.db_conn
+            .prepare(&query)
+            .map_err(|_| Error::MissingTable(String::from(kind.table())))?;
+
+        let rows: Vec<(i64, String, bool)> = statement
+            .query_map(&[], |row| {
+                let id: i64 = row.get(0);
+                let name: String = row.get(1);
+                let fixed: Option<bool> = row.get(2);
+                (id, name, fixed.unwrap_or(false))
+            })?
+            .collect::<::rusqlite::Result<Vec<(i64, String, bool)>>>()?;
+
+        let mut assets: Vec<Asset> = Vec::with_capacity(rows.len());
+        for (id, name, fixed) in rows {
+            let bytes =
+                read_blob(&self.db_conn, kind.table(), kind.column(), id)?;
+            assets.push(Asset { name, bytes, fixed });
+        }
+
+        Ok(assets)
+    }
+}
+
+/*
+ * Older game databases lack some columns of today's tables
+ */
+pub fn has_column(
+    db_conn: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<bool> {
+    let query = format!("pragma table_info({});", table);
+    let mut statement = db_conn.prepare(&query)?;
+    let columns: Vec<String> = statement
+        .query_map(&[], |row| {
+            let name: String = row.get(1);
+            name
+        })?
+        .collect::<::rusqlite::Result<Vec<String>>>()?;
+
+    Ok(columns.iter().any(|name| name == column))
+}
+//}}}
+
+//{{{ DirectoryAssets
+/*
+ * Loose files of the `data` directory, as they are kept in the repository
+ */
+pub struct DirectoryAssets {
+    root: PathBuf,
+    cards_dir: PathBuf,
+}
+impl DirectoryAssets {
+    pub fn new<P: Into<PathBuf>>(root: P) -> DirectoryAssets {
+        let root: PathBuf = root.into();
+        DirectoryAssets {
+            cards_dir: root.join("cards"),
+            root,
+        }
+    }
+
+    /*
+     * Card files are taken from `cards_dir` instead of `<root>/cards`
+     */
+    pub fn with_cards_dir<P: Into<PathBuf>>(
+        mut self,
+        cards_dir: P,
+    ) -> DirectoryAssets {
+        self.cards_dir = cards_dir.into();
+        self
+    }
+
+    fn read_dir(&self, dir: &Path, extensions: &[&str]) -> Result<Vec<Asset>> {
+        let dir = dir.to_path_buf();
+        let entries =
+            fs::read_dir(&dir).map_err(|_| Error::CannotRead(dir.clone()))?;
+
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|_| Error::CannotRead(dir.clone()))?
+                .path();
+            let extension_fits = match path.extension() {
+                Some(extension) => extensions
+                    .iter()
+                    .any(|e| extension.to_string_lossy() == *e),
+                None => extensions.is_empty(),
+            };
+            if path.is_file() && extension_fits {
+                paths.push(path);
+            }
+        }
+        // field2 goes before field10
+        paths.sort_by_key(|path| {
+            let name = path.to_string_lossy().into_owned();
+            (name.len(), name)
+        });
+
+        let mut assets: Vec<Asset> = Vec::with_capacity(paths.len());
+        for path in paths {
+            let file_name = path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let bytes =
+                fs::read(&path).map_err(|_| Error::CannotRead(path.clone()))?;
+            assets.push(Asset {
+                name: asset_name(&file_name),
+                bytes,
+                fixed: path.extension().map_or(false, |e| e == "fixed"),
+            });
+        }
+
+        Ok(assets)
+    }
+}
+impl AssetSource for DirectoryAssets {
+    fn load(&self, kind: AssetKind) -> Result<Vec<Asset>> {
+        match kind {
+            AssetKind::Image => {
+                let mut images =
+                    self.read_dir(&self.root.join("tiles"), &["png"])?;
+                images
+                    .extend(self.read_dir(&self.root.join("icons"), &["png"])?);
+                images.extend(self.read_dir(&self.root, &["png"])?);
+                Ok(images)
+            }
+            AssetKind::Font => self.read_dir(&self.root, &["ttf"]),
+            AssetKind::SoundEffect => {
+                self.read_dir(&self.root.join("sounds"), &["wav"])
+            }
+            AssetKind::Music => {
+                self.read_dir(&self.root.join("sounds"), &["mp3", "ogg"])
+            }
+            AssetKind::Card => {
+                let mut cards = self.read_dir(&self.cards_dir, &[])?;
+                // `field7.fixed` is the card `field7`, used as drawn,
+                // like `cards.fixed`
+                for mut card in self.read_dir(&self.cards_dir, &["fixed"])? {
+                    let name_length = card.name.len() - ".fixed".len();
+                    card.name.truncate(name_length);
+                    cards.push(card);
+                }
+                cards.sort_by_key(|card| (card.name.len(), card.name.clone()));
+                Ok(cards)
+            }
+        }
+    }
+}
+
+/*
+ * Some files are named in the database differently,
+ * see src/init_database.sql
+ */
+fn asset_name(file_name: &str) -> String {
+    String::from(match file_name {
+        "match_out.wav" => "resource_refill.wav",
+        "fizzing.wav" => "resource_gone.wav",
+        "monster_shout.wav" => "monster_died.wav",
+        "kobold_step.wav" => "monster_step1.wav",
+        "kobold_step2.wav" => "monster_step2.wav",
+        "kobold_breath.wav" => "monster_breath1.wav",
+        "kobold_breath2.wav" => "monster_breath2.wav",
+        "scene_controls.png" => "controls",
+        _ => file_name,
+    })
+}
+//}}}
+
+//{{{ MemoryAssets
+/*
+ * Assets kept in memory, mostly for tests
+ */
+pub struct MemoryAssets {
+    assets: HashMap<AssetKind, Vec<Asset>>,
+}
+impl MemoryAssets {
+    pub fn new() -> MemoryAssets {
+        MemoryAssets {
+            assets: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, kind: AssetKind, name: &str, bytes: Vec<u8>) {
+        self.insert_asset(
+            kind,
+            Asset {
+                name: String::from(name),
+                bytes,
+                fixed: false,
+            },
+        );
+    }
+
+    pub fn insert_asset(&mut self, kind: AssetKind, asset: Asset) {
+        self.assets.entry(kind).or_insert_with(Vec::new).push(asset);
+    }
+}
+impl AssetSource for MemoryAssets {
+    fn load(&self, kind: AssetKind) -> Result<Vec<Asset>> {
+        match self.assets.get(&kind) {
+            Some(assets) => Ok(assets.clone()),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::testing::temp_dir;
+
+    #[test]
+    fn cards_are_read_from_directory() {
+        let assets = DirectoryAssets::new("data");
+        let cards = assets.load(AssetKind::Card).unwrap();
+        let names: Vec<&str> =
+            cards.iter().map(|card| card.name.as_str()).collect();
+
+        assert_eq!(cards.len(), 18);
+        assert_eq!(names[0], "field1");
+        assert_eq!(names[9], "field10");
+    }
+
+    #[test]
+    fn fixed_cards_keep_their_names() {
+        let dir = temp_dir("cards");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("field2"), "_").unwrap();
+        fs::write(dir.join("field1.fixed"), "_").unwrap();
+
+        let assets = DirectoryAssets::new("data").with_cards_dir(&dir);
+        let cards = assets.load(AssetKind::Card).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let cards: Vec<(&str, bool)> = cards
+            .iter()
+            .map(|card| (card.name.as_str(), card.fixed))
+            .collect();
+        assert_eq!(cards, [("field1", true), ("field2", false)]);
+    }
+
+    #[test]
+    fn missing_directory_is_reported() {
+        let assets = DirectoryAssets::new("data").with_cards_dir("no-cards");
+        match assets.load(AssetKind::Card) {
+            Err(Error::CannotRead(path)) => {
+                assert_eq!(path, Path::new("no-cards"))
+            }
+            other => panic!("{:?} instead of CannotRead", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn directory_names_match_database() {
+        let assets = DirectoryAssets::new("data");
+        let effects = assets.load(AssetKind::SoundEffect).unwrap();
+
+        for name in [
+            "resource_refill.wav",
+            "resource_gone.wav",
+            "monster_died.wav",
+            "monster_step1.wav",
+            "monster_step2.wav",
+            "monster_breath1.wav",
+            "monster_breath2.wav",
+        ].iter()
+        {
+            assert!(effects.iter().any(|effect| effect.name == *name));
+        }
+    }
+
+    #[test]
+    fn memory_assets() {
+        let mut assets = MemoryAssets::new();
+        assets.insert(AssetKind::Music, "first.mp3", vec![1]);
+        assets.insert(AssetKind::Music, "second.mp3", vec![2]);
+
+        assert_eq!(assets.load_first(AssetKind::Music).unwrap().bytes, [1]);
+        assert!(assets.load(AssetKind::Image).unwrap().is_empty());
+        assert!(assets.load_first(AssetKind::Font).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+extern crate rusqlite;
+extern crate sevend;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use rusqlite::{Connection, OpenFlags};
+
+use sevend::assets::{has_column, Asset, AssetKind, AssetSource};
+use sevend::assets::{DirectoryAssets, SqliteAssets};
+use sevend::database_path;
+use sevend::map::{read_cards, Slot};
+use sevend::{Error, Result};
+
+const USAGE: &'static str = "Usage: kobald-cards <command> [options]
+
+Looks after the map cards, in a directory of card files
+and in the cards table of the game database.
+
+Commands:
+    check    report every problem of the cards
+    list     print a CSV line with the sides and slots of every card
+    diff     compare the cards of the directory and of the database
+    import   replace the cards of the database with the directory ones
+    export   write the cards of the database to the directory,
+             removing the card files the database no longer has
+
+Options:
+    --db <path>     game database, data.sqlite3 by default
+    --cards <dir>   card files, data/cards by default;
+                    check and list read the database unless it is given
+    --help          show this message";
+
+const CSV_HEADER: &'static str =
+    "name,side,fixed,top,right,bottom,left,obstacles,oil,curios,spawns,maybe";
+
+//{{{ Options
+#[derive(Debug, PartialEq)]
+enum Command {
+    Check,
+    List,
+    Diff,
+    Import,
+    Export,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Option<Command>,
+    db_path: PathBuf,
+    cards_dir: Option<PathBuf>,
+    help: bool,
+}
+impl Options {
+    fn cards_dir(&self) -> PathBuf {
+        match self.cards_dir {
+            Some(ref dir) => dir.clone(),
+            None => PathBuf::from("data").join("cards"),
+        }
+    }
+}
+
+fn parse_options<I>(args: I) -> ::std::result::Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options {
+        command: None,
+        db_path: database_path(None),
+        cards_dir: None,
+        help: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        let command = match arg.as_str() {
+            "--db" => {
+                options.db_path = PathBuf::from(value("--db")?);
+                continue;
+            }
+            "--cards" => {
+                options.cards_dir = Some(PathBuf::from(value("--cards")?));
+                continue;
+            }
+            "--help" | "-h" => {
+                options.help = true;
+                continue;
+            }
+            "check" => Command::Check,
+            "list" => Command::List,
+            "diff" => Command::Diff,
+            "import" => Command::Import,
+            "export" => Command::Export,
+            _ => return Err(format!("Unknown option {}", arg)),
+        };
+        if options.command.is_some() {
+            return Err(String::from("Only one command at a time"));
+        }
+        options.command = Some(command);
+    }
+
+    if options.command.is_none() && !options.help {
+        return Err(String::from("No command given"));
+    }
+    Ok(options)
+}
+//}}}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(reason) => {
+            eprintln!("{}\n\n{}", reason, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    match run(&options) {
+        Ok(true) => (),
+        // Problems or differences were found and printed
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("Cannot handle the cards. {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/*
+ * False when the cards have problems or differ
+ */
+fn run(options: &Options) -> Result<bool> {
+    let directory =
+        DirectoryAssets::new("data").with_cards_dir(options.cards_dir());
+    let load_directory = || directory.load(AssetKind::Card);
+    let load_database = || -> Result<Vec<Asset>> {
+        SqliteAssets::open(&options.db_path)?.load(AssetKind::Card)
+    };
+    let load_chosen = || match options.cards_dir {
+        Some(_) => load_directory(),
+        None => load_database(),
+    };
+
+    match options.command {
+        Some(Command::Check) => Ok(check(&load_chosen()?)),
+        Some(Command::List) => {
+            list(&load_chosen()?);
+            Ok(true)
+        }
+        Some(Command::Diff) => {
+            let differences = diff(
+                &load_directory()?,
+                &load_database()?,
+                &options.cards_dir().display().to_string(),
+                &options.db_path.display().to_string(),
+            );
+            for line in differences.iter() {
+                println!("{}", line);
+            }
+            Ok(differences.is_empty())
+        }
+        Some(Command::Import) => {
+            let cards = load_directory()?;
+            if !check(&cards) {
+                return Ok(false);
+            }
+            import(&cards, &options.db_path)?;
+            println!(
+                "{} cards imported into {}",
+                cards.len(),
+                options.db_path.display()
+            );
+            Ok(true)
+        }
+        Some(Command::Export) => {
+            let cards = load_database()?;
+            let removed = export(&cards, &options.cards_dir())?;
+            println!(
+                "{} cards exported to {}",
+                cards.len(),
+                options.cards_dir().display()
+            );
+            for name in removed.iter() {
+                println!("{}: removed, not in the database", name);
+            }
+            Ok(true)
+        }
+        None => Ok(true),
+    }
+}
+
+//{{{ Commands
+/*
+ * Prints the problems, true when there are none
+ */
+fn check(card_assets: &[Asset]) -> bool {
+    let (cards, errors) = read_cards(card_assets);
+    for error in errors.iter() {
+        eprintln!("{}", error);
+    }
+    if errors.is_empty() {
+        println!("{} cards are fine", cards.len());
+    }
+    errors.is_empty()
+}
+
+fn list(card_assets: &[Asset]) {
+    let (cards, errors) = read_cards(card_assets);
+    for error in errors.iter() {
+        eprintln!("{}", error);
+    }
+
+    println!("{}", CSV_HEADER);
+    for card in cards.iter() {
+        let border = card.border();
+        let slots: Vec<String> = [
+            Slot::Obstacle,
+            Slot::Oil,
+            Slot::Curio,
+            Slot::KoboldSpawn,
+            Slot::MaybeWall,
+        ].iter()
+            .map(|&slot| card.slot_count(slot).to_string())
+            .collect();
+        println!(
+            "{},{},{},{},{}",
+            card.name(),
+            card.side(),
+            card.is_fixed(),
+            border.join(","),
+            slots.join(",")
+        );
+    }
+}
+
+/*
+ * Cards are matched by name. Lines are compared as text,
+ * so the line endings do not matter.
+ */
+fn diff(
+    directory: &[Asset],
+    database: &[Asset],
+    directory_name: &str,
+    database_name: &str,
+) -> Vec<String> {
+    let mut differences: Vec<String> = Vec::new();
+    let database_cards: HashMap<&str, &Asset> = database
+        .iter()
+        .map(|card| (card.name.as_str(), card))
+        .collect();
+
+    for card in directory.iter() {
+        let other = match database_cards.get(card.name.as_str()) {
+            Some(other) => other,
+            None => {
+                differences.push(format!(
+                    "{}: only in {}",
+                    card.name, directory_name
+                ));
+                continue;
+            }
+        };
+
+        if card.fixed != other.fixed {
+            differences.push(format!(
+                "{}: {} in {}, {} in {}",
+                card.name,
+                fixed_name(card.fixed),
+                directory_name,
+                fixed_name(other.fixed),
+                database_name
+            ));
+        }
+
+        let text = String::from_utf8_lossy(&card.bytes);
+        let other_text = String::from_utf8_lossy(&other.bytes);
+        let lines: Vec<&str> = text.lines().collect();
+        let other_lines: Vec<&str> = other_text.lines().collect();
+        for line_n in 0..lines.len().max(other_lines.len()) {
+            let line = lines.get(line_n).cloned().unwrap_or("");
+            let other_line = other_lines.get(line_n).cloned().unwrap_or("");
+            if line != other_line {
+                differences.push(format!(
+                    "{}: line {}: '{}' in {}, '{}' in {}",
+                    card.name,
+                    line_n + 1,
+                    line,
+                    directory_name,
+                    other_line,
+                    database_name
+                ));
+            }
+        }
+    }
+
+    for card in database.iter() {
+        if !directory.iter().any(|other| other.name == card.name) {
+            differences.push(format!(
+                "{}: only in {}",
+                card.name, database_name
+            ));
+        }
+    }
+
+    differences
+}
+
+fn fixed_name(fixed: bool) -> &'static str {
+    if fixed {
+        "fixed"
+    } else {
+        "free"
+    }
+}
+
+/*
+ * All cards of the table are replaced, in one transaction
+ */
+fn import(card_assets: &[Asset], db_path: &Path) -> Result<()> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_WRITE;
+    let mut db_conn = Connection::open_with_flags(db_path, flags)
+        .map_err(|_| Error::MissingDatabase(db_path.to_path_buf()))?;
+
+    let transaction = db_conn.transaction()?;
+    // Databases made before cards had names or could be fixed
+    // get the columns, so the cards keep both
+    let table = AssetKind::Card.table();
+    if !has_column(&transaction, table, "name")? {
+        transaction.execute_batch(
+            &(String::from("alter table ") + table + " add column name TEXT;"),
+        )?;
+    }
+    if !has_column(&transaction, table, "fixed")? {
+        transaction.execute_batch(
+            &(String::from("alter table ") + table
+                + " add column fixed INTEGER DEFAULT 0;"),
+        )?;
+    }
+    transaction.execute(
+        &(String::from("delete from ") + table + ";"),
+        &[],
+    )?;
+    for card in card_assets.iter() {
+        transaction.execute(
+            &(String::from("insert into ") + table
+                + "(name, tiles, fixed) values (?, ?, ?);"),
+            &[&card.name, &card.bytes, &card.fixed],
+        )?;
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/*
+ * Fixed cards get the `.fixed` extension, the way
+ * the directory is read. Cards of the directory missing from the database
+ * are removed, so the next import does not bring them back.
+ * Returns the names of the removed cards.
+ */
+fn export(card_assets: &[Asset], dir: &Path) -> Result<Vec<String>> {
+    let cannot_write = |path: &Path| Error::CannotWrite(path.to_path_buf());
+    fs::create_dir_all(dir).map_err(|_| cannot_write(dir))?;
+
+    let mut removed: Vec<String> = Vec::new();
+    let old_cards = DirectoryAssets::new("data")
+        .with_cards_dir(dir)
+        .load(AssetKind::Card)?;
+    for old_card in old_cards.iter() {
+        if card_assets.iter().any(|card| card.name == old_card.name) {
+            continue;
+        }
+        let path = if old_card.fixed {
+            dir.join(&old_card.name).with_extension("fixed")
+        } else {
+            dir.join(&old_card.name)
+        };
+        fs::remove_file(&path).map_err(|_| cannot_write(&path))?;
+        removed.push(old_card.name.clone());
+    }
+
+    for card in card_assets.iter() {
+        let free_path = dir.join(&card.name);
+        let fixed_path = free_path.with_extension("fixed");
+        let (path, stale_path) = if card.fixed {
+            (fixed_path, free_path)
+        } else {
+            (free_path, fixed_path)
+        };
+
+        fs::write(&path, &card.bytes).map_err(|_| cannot_write(&path))?;
+        // The card could have been fixed or freed since the last export
+        if stale_path.exists() {
+            fs::remove_file(&stale_path)
+                .map_err(|_| cannot_write(&stale_path))?;
+        }
+    }
+
+    Ok(removed)
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(name: &str, text: &str, fixed: bool) -> Asset {
+        Asset {
+            name: String::from(name),
+            bytes: text.as_bytes().to_vec(),
+            fixed,
+        }
+    }
+
+    #[test]
+    fn options() {
+        let parse = |args: &[&str]| {
+            parse_options(args.iter().map(|arg| String::from(*arg)))
+        };
+
+        let options = parse(&["diff", "--cards", "new_cards"]).unwrap();
+        assert_eq!(options.command, Some(Command::Diff));
+        assert_eq!(options.cards_dir(), PathBuf::from("new_cards"));
+        assert_eq!(
+            parse(&["list"]).unwrap().cards_dir(),
+            PathBuf::from("data").join("cards")
+        );
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["check", "list"]).is_err());
+        assert!(parse(&["--db"]).is_err());
+    }
+
+    #[test]
+    fn cards_in_data_are_fine() {
+        let cards = DirectoryAssets::new("data")
+            .load(AssetKind::Card)
+            .unwrap();
+        assert!(check(&cards));
+    }
+
+    #[test]
+    fn differences() {
+        let directory = [
+            card("field1", "#_\n__\n", false),
+            card("field2", "#_\n__\n", true),
+            card("field3", "#_\n__\n", false),
+        ];
+        let database = [
+            card("field1", "#_\r\n__", false),
+            card("field2", "#_\n_#\n", false),
+            card("field4", "#_\n__\n", false),
+        ];
+
+        assert_eq!(
+            diff(&directory, &database, "dir", "db"),
+            [
+                "field2: fixed in dir, free in db",
+                "field2: line 2: '__' in dir, '_#' in db",
+                "field3: only in dir",
+                "field4: only in db",
+            ]
+        );
+    }
+
+    #[test]
+    fn export_removes_deleted_cards() {
+        let dir = env::temp_dir()
+            .join(format!("kobald-{}-export", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("field1"), "_").unwrap();
+        fs::write(dir.join("field2.fixed"), "_").unwrap();
+        fs::write(dir.join("field3"), "_").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let removed = export(&[card("field1", "#", true)], &dir).unwrap();
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(removed, ["field2", "field3"]);
+        assert_eq!(files, ["field1.fixed", "notes.txt"]);
+    }
+
+    #[test]
+    fn import_into_old_database() {
+        let dir = env::temp_dir()
+            .join(format!("kobald-{}-old-db", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("game.db");
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "create table cards(tiles BLOB);
+                insert into cards(tiles) values ('_');",
+            )
+            .unwrap();
+
+        let cards = [card("field1", "#", true), card("field2", "_", false)];
+        import(&cards, &db_path).unwrap();
+        let imported = SqliteAssets::open(&db_path)
+            .unwrap()
+            .load(AssetKind::Card)
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let imported: Vec<(&str, &[u8], bool)> = imported
+            .iter()
+            .map(|card| (card.name.as_str(), &card.bytes[..], card.fixed))
+            .collect();
+        assert_eq!(
+            imported,
+            [("field1", &b"#"[..], true), ("field2", &b"_"[..], false)]
+        );
+    }
+}
@@ -0,0 +1,338 @@
+extern crate rusqlite;
+extern crate sevend;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use rusqlite::Connection;
+
+use sevend::assets::{AssetSource, DirectoryAssets, SqliteAssets};
+use sevend::graphics::export_png;
+use sevend::map::{Map, TileType};
+use sevend::objects::place_objects;
+use sevend::settings::Settings;
+use sevend::{database_path, open_database_at};
+use sevend::{Error, GameRng, Result};
+
+const USAGE: &'static str = "Usage: kobald-mapgen [options]
+
+Generates maps the way the game does and prints a CSV line for each one.
+SDL is used only for --png.
+
+Options:
+    --seed <number>   seed of the first map, 0 by default
+    --count <number>  how many maps, one seed after another, 100 by default
+    --db <path>       read settings, messages and cards from this database
+    --cards <dir>     read cards from this directory instead of the database
+    --ascii <dir>     also write every map as text to <dir>/<seed>.txt
+    --png <dir>       also draw every map to <dir>/<seed>.png
+    --help            show this message";
+
+const CSV_HEADER: &'static str =
+    "seed,tries,passable,path_length,resources,obstacles,reasons";
+
+//{{{ Options
+#[derive(Debug, PartialEq)]
+struct Options {
+    first_seed: usize,
+    count: usize,
+    db_path: PathBuf,
+    cards_dir: Option<PathBuf>,
+    ascii_dir: Option<PathBuf>,
+    png_dir: Option<PathBuf>,
+    help: bool,
+}
+
+fn parse_options<I>(args: I) -> ::std::result::Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options {
+        first_seed: 0,
+        count: 100,
+        db_path: database_path(None),
+        cards_dir: None,
+        ascii_dir: None,
+        png_dir: None,
+        help: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        let number = |name: &str, text: String| {
+            text.parse::<usize>()
+                .map_err(|_| format!("{} {} is not a number", name, text))
+        };
+        match arg.as_str() {
+            "--seed" => {
+                options.first_seed = number("Seed", value("--seed")?)?;
+            }
+            "--count" => options.count = number("Count", value("--count")?)?,
+            "--db" => options.db_path = PathBuf::from(value("--db")?),
+            "--cards" => {
+                options.cards_dir = Some(PathBuf::from(value("--cards")?))
+            }
+            "--ascii" => {
+                options.ascii_dir = Some(PathBuf::from(value("--ascii")?))
+            }
+            "--png" => options.png_dir = Some(PathBuf::from(value("--png")?)),
+            "--help" | "-h" => options.help = true,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+
+    Ok(options)
+}
+//}}}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(reason) => {
+            eprintln!("{}\n\n{}", reason, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(error) = run(&options) {
+        eprintln!("Cannot generate maps. {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<()> {
+    let db_connection = open_database_at(&options.db_path)?;
+    let settings = Settings::load(&db_connection)?;
+    let assets: Box<AssetSource> = match options.cards_dir {
+        Some(ref dir) => {
+            Box::new(DirectoryAssets::new("data").with_cards_dir(dir.clone()))
+        }
+        None => Box::new(SqliteAssets::open(&options.db_path)?),
+    };
+    // Tile images are always taken from the database
+    let images = SqliteAssets::open(&options.db_path)?;
+
+    for dir in options.ascii_dir.iter().chain(options.png_dir.iter()) {
+        fs::create_dir_all(dir).map_err(|_| cannot_write(dir))?;
+    }
+
+    println!("{}", CSV_HEADER);
+    for seed in options.first_seed..options.first_seed + options.count {
+        let generated = generate(seed, &*assets, &db_connection, &settings)?;
+        println!("{}", generated.csv_line());
+
+        if let Some(ref map) = generated.map {
+            let file = |dir: &Path, extension: &str| {
+                dir.join(seed.to_string()).with_extension(extension)
+            };
+            if let Some(ref dir) = options.ascii_dir {
+                let path = file(dir, "txt");
+                fs::write(&path, map.to_ascii(&generated.oil))
+                    .map_err(|_| cannot_write(&path))?;
+            }
+            if let Some(ref dir) = options.png_dir {
+                export_png(map, &generated.oil, &images, &file(dir, "png"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn cannot_write(path: &Path) -> Error {
+    Error::CannotWrite(path.to_path_buf())
+}
+
+//{{{ Generated
+struct Generated {
+    seed: usize,
+    // None when every try failed
+    map: Option<Map>,
+    reasons: Vec<String>,
+    oil: Vec<(usize, usize)>,
+}
+impl Generated {
+    /*
+     * Maps which were not generated at all have only tries and reasons
+     */
+    fn csv_line(&self) -> String {
+        let reasons = csv_field(&self.reasons.join("; "));
+        let map = match self.map {
+            Some(ref map) => map,
+            None => {
+                return format!(
+                    "{},{},,,,,{}",
+                    self.seed,
+                    self.reasons.len(),
+                    reasons
+                )
+            }
+        };
+
+        let tiles = map.tiles.iter().flat_map(|column| column.iter());
+        let passable = tiles.clone().filter(|tile| tile.passable).count();
+        let obstacles = tiles
+            .filter(|tile| match tile.ttype {
+                TileType::Obstacle => true,
+                _ => false,
+            })
+            .count();
+
+        let path_length =
+            match (map.get_location("start"), map.get_location("children")) {
+                (Some(start), Some(children)) => {
+                    match map.get_path_distance(&start, &children) {
+                        Some(length) => length.to_string(),
+                        None => String::new(),
+                    }
+                }
+                _ => String::new(),
+            };
+
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.seed,
+            self.reasons.len() + 1,
+            passable,
+            path_length,
+            self.oil.len(),
+            obstacles,
+            reasons
+        )
+    }
+}
+
+fn generate(
+    seed: usize,
+    assets: &AssetSource,
+    db_connection: &Connection,
+    settings: &Settings,
+) -> Result<Generated> {
+    let mut rng = GameRng::new(seed);
+    let generated =
+        Map::generate_with_reasons(assets, db_connection, settings, &mut rng);
+
+    match generated {
+        Ok((map, reasons)) => {
+            let (_player, _monster, resources) =
+                place_objects(&map, settings, &mut rng);
+            Ok(Generated {
+                seed,
+                map: Some(map),
+                reasons,
+                oil: resources.locations().to_vec(),
+            })
+        }
+        Err(Error::MapGenerationExhausted(reasons)) => Ok(Generated {
+            seed,
+            map: None,
+            reasons,
+            oil: Vec::new(),
+        }),
+        Err(error) => Err(error),
+    }
+}
+//}}}
+
+fn csv_field(text: &str) -> String {
+    if text.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        String::from(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sevend::assets::{AssetKind, MemoryAssets};
+    use sevend::game::GameState;
+
+    fn messages_db() -> Connection {
+        let db_connection = Connection::open_in_memory().unwrap();
+        db_connection
+            .execute_batch(
+                "create table messages(situation TEXT, message TEXT);
+                insert into messages(situation, message)
+                    values ('obstacle1', '');",
+            )
+            .unwrap();
+        db_connection
+    }
+
+    #[test]
+    fn options() {
+        let parse = |args: &[&str]| {
+            parse_options(args.iter().map(|arg| String::from(*arg)))
+        };
+
+        let options = parse(&["--seed", "10", "--count", "5"]).unwrap();
+        assert_eq!((options.first_seed, options.count), (10, 5));
+        assert!(parse(&["--count", "-1"]).is_err());
+        assert!(parse(&["--map"]).is_err());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn csv_line() {
+        let db_connection = messages_db();
+        let assets = DirectoryAssets::new("data");
+
+        let generated =
+            generate(5, &assets, &db_connection, &Settings::default())
+                .unwrap();
+        let line = generated.csv_line();
+        let fields: Vec<&str> = line.split(',').collect();
+
+        assert_eq!(fields.len(), 7);
+        assert_eq!(fields[0], "5");
+        assert!(fields[1].parse::<usize>().unwrap() >= 1);
+        assert!(fields[2].parse::<usize>().unwrap() > 0);
+        assert_eq!(fields[4], generated.oil.len().to_string());
+    }
+
+    #[test]
+    fn oil_is_where_the_game_puts_it() {
+        let db_connection = messages_db();
+        // Spawn spots make the kobold draw from the RNG before the oil
+        let mut assets = MemoryAssets::new();
+        for mut card in DirectoryAssets::new("data")
+            .load(AssetKind::Card)
+            .unwrap()
+        {
+            let tiles = String::from_utf8(card.bytes).unwrap();
+            card.bytes = tiles.replacen("_", "K", 1).into_bytes();
+            assets.insert_asset(AssetKind::Card, card);
+        }
+        let settings = Settings::default();
+
+        for seed in 0..5 {
+            let generated =
+                generate(seed, &assets, &db_connection, &settings).unwrap();
+            let game = GameState::init_with_seed(
+                seed,
+                &assets,
+                &db_connection,
+                &settings,
+            ).unwrap();
+            assert_eq!(generated.oil, game.resources.locations());
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+use std::result;
+
+use rusqlite;
+
+use super::save::SAVE_VERSION;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    // Database file cannot be opened
+    MissingDatabase(PathBuf),
+    // Table is not present in the database
+    MissingTable(String),
+    // Row is not present in the table
+    MissingRow { table: String, row: String },
+    // Blob is present but cannot be read or decoded
+    CorruptBlob { table: String, row: String },
+    // Card text cannot be turned into tiles
+    BadCardLayout { card: String, reason: String },
+    // Field of cards is bigger than the number of cards
+    TooFewCards { needed: usize, found: usize },
+    // Text picture of a map cannot be turned into tiles
+    BadAsciiMap(String),
+    // Every try of map generation failed, reason of each one is kept
+    MapGenerationExhausted(Vec<String>),
+    // Value of the game setting is not usable
+    InvalidSetting { setting: String, reason: String },
+    // Save file was written by another version of the game
+    IncompatibleSave { path: PathBuf, version: i64 },
+    // File or directory of assets cannot be read
+    CannotRead(PathBuf),
+    // File cannot be written or removed
+    CannotWrite(PathBuf),
+    // Replay file cannot be read, written or understood
+    BadReplay { path: PathBuf, reason: String },
+    // SDL or one of its subsystems failed
+    Sdl { subsystem: String, reason: String },
+    // Any other database failure
+    Database(rusqlite::Error),
+}
+impl Error {
+    pub fn sdl<E: fmt::Display>(subsystem: &str, error: E) -> Error {
+        Error::Sdl {
+            subsystem: String::from(subsystem),
+            reason: error.to_string(),
+        }
+    }
+
+    pub fn missing_row<R: fmt::Display>(table: &str, row: R) -> Error {
+        Error::MissingRow {
+            table: String::from(table),
+            row: row.to_string(),
+        }
+    }
+
+    pub fn corrupt_blob<R: fmt::Display>(table: &str, row: R) -> Error {
+        Error::CorruptBlob {
+            table: String::from(table),
+            row: row.to_string(),
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MissingDatabase(ref path) => {
+                write!(f, "Cannot open game data at {}", path.display())
+            }
+            Error::MissingTable(ref table) => {
+                write!(f, "Game data has no '{}' table", table)
+            }
+            Error::MissingRow { ref table, ref row } => {
+                write!(f, "Table '{}' has no '{}' row", table, row)
+            }
+            Error::CorruptBlob { ref table, ref row } => {
+                write!(f, "Cannot read '{}' from table '{}'", row, table)
+            }
+            Error::BadCardLayout {
+                ref card,
+                ref reason,
+            } => {
+                write!(f, "Card {} is malformed: {}", card, reason)
+            }
+            Error::TooFewCards { needed, found } => write!(
+                f,
+                "{} cards are needed for the field, {} found",
+                needed, found
+            ),
+            Error::BadAsciiMap(ref reason) => {
+                write!(f, "Text map is malformed: {}", reason)
+            }
+            Error::MapGenerationExhausted(ref reasons) => {
+                let tries = reasons.len();
+                write!(f, "Map generation failed after {} tries", tries)?;
+                for (try_n, reason) in reasons.iter().enumerate() {
+                    write!(f, "\n  try {}: {}", try_n + 1, reason)?;
+                }
+                Ok(())
+            }
+            Error::InvalidSetting {
+                ref setting,
+                ref reason,
+            } => write!(f, "Setting '{}' is invalid: {}", setting, reason),
+            Error::IncompatibleSave { ref path, version } => write!(
+                f,
+                "Save {} has version {}, only version {} can be resumed",
+                path.display(),
+                version,
+                SAVE_VERSION
+            ),
+            Error::CannotRead(ref path) => {
+                write!(f, "Cannot read {}", path.display())
+            }
+            Error::CannotWrite(ref path) => {
+                write!(f, "Cannot write {}", path.display())
+            }
+            Error::BadReplay {
+                ref path,
+                ref reason,
+            } => write!(f, "Replay {} is unusable: {}", path.display(), reason),
+            Error::Sdl {
+                ref subsystem,
+                ref reason,
+            } => write!(f, "SDL {} failure: {}", subsystem, reason),
+            Error::Database(ref error) => {
+                write!(f, "Database error: {}", error)
+            }
+        }
+    }
+}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Database(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Error {
+        Error::Database(error)
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+use std::collections::vec_deque::Drain;
+
+//{{{ GameEvent
+/*
+ * Things that happened in the game after a command was applied.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    ResourceFound { index: usize },
+    ResourceGone,
+    ResourceRefill { success: bool },
+    ObstacleFound { text: String },
+    CurioFound { scene: String },
+    PlayerInDanger,
+    PlayerMeetMonster,
+    // The kobold moved or stood still, heard from where it is now
+    MonsterStep { location: (usize, usize) },
+    MonsterBreath { location: (usize, usize) },
+    MonsterDied,
+    GameOver,
+}
+//}}}
+
+//{{{ EventQueue
+/*
+ * First in, first out. Game objects push into it,
+ * frontends (or tests) pop from it.
+ */
+pub struct EventQueue {
+    events: VecDeque<GameEvent>,
+}
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue {
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: GameEvent) {
+        self.events.push_back(event);
+    }
+
+    pub fn pop(&mut self) -> Option<GameEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn drain<'a>(&'a mut self) -> Drain<'a, GameEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn contains(&self, event: &GameEvent) -> bool {
+        self.events.contains(event)
+    }
+}
+//}}}
@@ -0,0 +1,206 @@
+/*
+ * Field of view by recursive shadowcasting.
+ *
+ * The space around the origin is split into 8 octants, and each one
+ * is scanned row by row, going away from the origin. Opaque tiles
+ * cast shadows, which narrow the slopes scanned in the rows behind.
+ * Only tiles within a circle of `radius` are lit.
+ */
+
+// Multipliers turning the first octant into each of the 8:
+// x = dx * xx + dy * xy, y = dx * yx + dy * yy
+const OCTANTS: [(isize, isize, isize, isize); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+/*
+ * Lit tiles as lit[x][y], the way map tiles are kept.
+ * Opaque tiles are lit themselves, but hide what is behind them.
+ * Nothing is lit when the radius is 0.
+ */
+pub fn field_of_view<F>(
+    origin: (usize, usize),
+    radius: usize,
+    width: usize,
+    height: usize,
+    is_opaque: F,
+) -> Vec<Vec<bool>>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut caster = Caster {
+        origin: (origin.0 as isize, origin.1 as isize),
+        radius: radius as isize,
+        width: width as isize,
+        height: height as isize,
+        is_opaque,
+        lit: vec![vec![false; height]; width],
+    };
+    if radius == 0 || origin.0 >= width || origin.1 >= height {
+        return caster.lit;
+    }
+
+    caster.lit[origin.0][origin.1] = true;
+    for octant in OCTANTS.iter() {
+        caster.cast_light(1, 1.0, 0.0, *octant);
+    }
+
+    caster.lit
+}
+
+struct Caster<F: Fn(usize, usize) -> bool> {
+    origin: (isize, isize),
+    radius: isize,
+    width: isize,
+    height: isize,
+    is_opaque: F,
+    lit: Vec<Vec<bool>>,
+}
+impl<F: Fn(usize, usize) -> bool> Caster<F> {
+    /*
+     * Scans rows from `row` on, between the slopes `start` and `end`.
+     * Slopes go from 1 (diagonal) down to 0 (straight axis).
+     */
+    fn cast_light(
+        &mut self,
+        row: isize,
+        mut start: f64,
+        end: f64,
+        octant: (isize, isize, isize, isize),
+    ) {
+        if start < end {
+            return;
+        }
+        let (xx, xy, yx, yy) = octant;
+        let radius_squared = self.radius * self.radius;
+        let mut new_start: f64 = 0.0;
+
+        for distance in row..self.radius + 1 {
+            let dy = -distance;
+            let mut blocked = false;
+
+            for dx in -distance..1 {
+                let left_slope = (dx as f64 - 0.5) / (dy as f64 + 0.5);
+                let right_slope = (dx as f64 + 0.5) / (dy as f64 - 0.5);
+                if start < right_slope {
+                    continue;
+                } else if end > left_slope {
+                    break;
+                }
+
+                let x = self.origin.0 + dx * xx + dy * xy;
+                let y = self.origin.1 + dx * yx + dy * yy;
+                let inside =
+                    x >= 0 && y >= 0 && x < self.width && y < self.height;
+                if inside && dx * dx + dy * dy <= radius_squared {
+                    self.lit[x as usize][y as usize] = true;
+                }
+                // Nothing is seen past the edge of the map
+                let opaque =
+                    !inside || (self.is_opaque)(x as usize, y as usize);
+
+                if blocked {
+                    if opaque {
+                        new_start = right_slope;
+                    } else {
+                        blocked = false;
+                        start = new_start;
+                    }
+                } else if opaque && distance < self.radius {
+                    // The shadow starts, what is beside it is scanned
+                    // separately
+                    blocked = true;
+                    self.cast_light(distance + 1, start, left_slope, octant);
+                    new_start = right_slope;
+                }
+            }
+
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * '#' is opaque, '@' is the origin; the result uses
+     * '*' for lit tiles and '.' for dark ones
+     */
+    fn view(rows: &[&str], radius: usize) -> Vec<String> {
+        let grid: Vec<Vec<char>> =
+            rows.iter().map(|row| row.chars().collect()).collect();
+        let (width, height) = (grid[0].len(), grid.len());
+        let mut origin = (0, 0);
+        for (y, row) in grid.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                if c == '@' {
+                    origin = (x, y);
+                }
+            }
+        }
+
+        let lit = field_of_view(origin, radius, width, height, |x, y| {
+            grid[y][x] == '#'
+        });
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| if lit[x][y] { '*' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn open_space_is_lit_in_a_circle() {
+        let rows = [
+            "_______", "_______", "_______", "___@___", "_______", "_______",
+            "_______",
+        ];
+        assert_eq!(
+            view(&rows, 3),
+            [
+                "...*...", ".*****.", ".*****.", "*******", ".*****.",
+                ".*****.", "...*...",
+            ]
+        );
+    }
+
+    #[test]
+    fn walls_cast_shadows() {
+        let rows = ["_______", "_@_#___", "_______"];
+        assert_eq!(view(&rows, 5), ["******.", "****...", "******."]);
+    }
+
+    #[test]
+    fn nothing_is_lit_without_light() {
+        let rows = ["___", "_@_", "___"];
+        assert_eq!(view(&rows, 0), ["...", "...", "..."]);
+    }
+
+    #[test]
+    fn corridor_around_a_corner_is_dark() {
+        let rows = [
+            "#####", //
+            "#@__#", //
+            "###_#", //
+            "#___#", //
+            "#####",
+        ];
+        assert_eq!(
+            view(&rows, 5),
+            ["*****", "*****", "*****", ".....", "....."]
+        );
+    }
+}
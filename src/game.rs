@@ -0,0 +1,317 @@
+use rusqlite::Connection;
+
+use super::map::Map;
+use super::objects::{place_objects, Kobold, Player, Resources};
+use super::graphics::{TextLine, TextScene};
+use super::events::{EventQueue, GameEvent};
+use super::{generate_seed, GameRng};
+use super::Result;
+use super::settings::Settings;
+use super::sound::MusicState;
+use super::assets::AssetSource;
+use super::save::{get_value, set_value};
+
+//{{{ Command
+/*
+ * Everything the player can ask the game to do.
+ * Frontends translate their input into these.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Move(isize, isize),
+    Refill,
+    ToggleMark(usize, usize),
+    ShowHelp,
+    CloseScene,
+}
+//}}}
+
+//{{{ GameState
+pub struct GameState {
+    pub map: Map,
+    pub player: Player,
+    pub monster: Kobold,
+    pub resources: Resources,
+    pub textline: TextLine,
+    pub textscene: TextScene,
+
+    rng: GameRng,
+    happy_end: bool,
+    end: bool,
+}
+impl GameState {
+    pub fn init(
+        assets: &AssetSource,
+        db_conn: &Connection,
+        settings: &Settings,
+    ) -> Result<GameState> {
+        let seed: usize = generate_seed();
+        println!("Map generation seed: {}", seed);
+        GameState::init_with_seed(seed, assets, db_conn, settings)
+    }
+
+    pub fn init_with_seed(
+        seed: usize,
+        assets: &AssetSource,
+        db_conn: &Connection,
+        settings: &Settings,
+    ) -> Result<GameState> {
+        let mut rng = GameRng::new(seed);
+        let map = Map::generate(assets, db_conn, settings, &mut rng)?;
+        GameState::with_map(map, rng, db_conn, settings)
+    }
+
+    pub fn with_map(
+        mut map: Map,
+        mut rng: GameRng,
+        db_conn: &Connection,
+        settings: &Settings,
+    ) -> Result<GameState> {
+        let (player, mut monster, resources) =
+            place_objects(&map, settings, &mut rng);
+        map.update(&player);
+        monster.look(&map);
+
+        Ok(GameState {
+            map,
+            player,
+            monster,
+            resources,
+            textline: TextLine::init(settings, db_conn)?,
+            textscene: TextScene::init(settings),
+            rng,
+            happy_end: false,
+            end: false,
+        })
+    }
+
+    /*
+     * See src/save.rs for the save file itself
+     */
+    pub fn save(&self, save_conn: &Connection) -> Result<()> {
+        set_value(save_conn, "seed", self.rng.seed() as i64)?;
+        set_value(save_conn, "rng_draws", self.rng.draws() as i64)?;
+        set_value(save_conn, "happy_end", self.happy_end)?;
+        set_value(save_conn, "end", self.end)?;
+
+        self.map.save(save_conn)?;
+        self.player.save(save_conn)?;
+        self.monster.save(save_conn)?;
+        self.resources.save(save_conn)?;
+        self.textline.save(save_conn)?;
+        self.textscene.save(save_conn)?;
+        Ok(())
+    }
+
+    pub fn load(
+        save_conn: &Connection,
+        db_conn: &Connection,
+        settings: &Settings,
+    ) -> Result<GameState> {
+        let seed: i64 = get_value(save_conn, "seed")?;
+        let draws: i64 = get_value(save_conn, "rng_draws")?;
+
+        let mut map = Map::load(save_conn)?;
+        let player = Player::load(save_conn, settings)?;
+        let mut monster = Kobold::load(save_conn, settings)?;
+        // What is in sight is not saved
+        map.update(&player);
+        monster.look(&map);
+
+        Ok(GameState {
+            map,
+            player,
+            monster,
+            resources: Resources::load(save_conn)?,
+            textline: TextLine::load(save_conn, settings, db_conn)?,
+            textscene: TextScene::load(save_conn, settings)?,
+            rng: GameRng::resume(seed as usize, draws as u64),
+            happy_end: get_value(save_conn, "happy_end")?,
+            end: get_value(save_conn, "end")?,
+        })
+    }
+
+    pub fn seed(&self) -> usize {
+        self.rng.seed()
+    }
+
+    pub fn is_happy_end(&self) -> bool {
+        self.happy_end
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.end
+    }
+
+    /*
+     * Endings first, then the danger, then the darkness
+     */
+    pub fn music_state(&self) -> MusicState {
+        if self.end {
+            MusicState::Ending
+        } else if self.player.is_in_danger() {
+            MusicState::Danger
+        } else if self.player.get_view_distance() == 0 {
+            MusicState::LampOut
+        } else {
+            MusicState::Exploring
+        }
+    }
+
+    pub fn apply(&mut self, command: Command) -> Vec<GameEvent> {
+        let mut events = EventQueue::new();
+
+        match command {
+            Command::CloseScene => self.close_scene(&mut events),
+            Command::ShowHelp => {
+                self.textscene.active = true;
+                self.textscene.scene = String::from("controls");
+            }
+            Command::ToggleMark(x, y) => self.map.toggle_mark(x, y),
+            _ => {
+                if !self.textscene.active {
+                    let mut player_events = EventQueue::new();
+
+                    // Update game
+                    if self.player.update(
+                        &command,
+                        &self.map,
+                        &self.monster,
+                        &self.resources,
+                        &mut player_events,
+                    ) {
+                        self.map.update(&self.player);
+                        self.textline.update();
+                        self.monster.update(
+                            &self.map,
+                            &self.player,
+                            &mut self.rng,
+                            &mut player_events,
+                        );
+                    }
+
+                    while let Some(event) = player_events.pop() {
+                        self.process_event(event, &mut events);
+                    }
+                }
+            }
+        }
+
+        events.drain().collect()
+    }
+
+    //{{{ close_scene
+    fn close_scene(&mut self, events: &mut EventQueue) {
+        if self.textscene.active {
+            self.textscene.active = false;
+
+            if self.textscene.scene.starts_with("end_") {
+                events.push(GameEvent::GameOver);
+                return;
+            }
+
+            if self.end {
+                self.textscene.active = true;
+                self.textscene.scene = if self.happy_end {
+                    String::from("end_good")
+                } else {
+                    String::from("end_bad")
+                }
+            }
+        }
+    }
+    //}}}
+
+    //{{{ process_event
+    fn process_event(&mut self, event: GameEvent, events: &mut EventQueue) {
+        match event {
+            GameEvent::ResourceRefill { success } => if success {
+                self.textline.set_situation("resource_refill");
+            } else {
+                self.textline.set_situation("resource_absent");
+            },
+            GameEvent::ResourceFound { index } => {
+                self.resources.remove(index);
+                self.player.add_view_resource_count();
+
+                self.textline
+                    .set_any_situation("resource_found", &mut self.rng);
+            }
+            GameEvent::ResourceGone => {
+                self.textline.set_any_situation("resource_gone", &mut self.rng);
+            }
+            GameEvent::ObstacleFound { ref text } => {
+                self.textline.set_situation(text);
+            }
+            GameEvent::CurioFound { ref scene } => {
+                self.textscene.active = true;
+                self.textscene.scene = scene.clone();
+                match scene.as_str() {
+                    "item" => {
+                        self.monster.die();
+                        self.happy_end = true;
+                        events.push(GameEvent::MonsterDied);
+                    }
+                    "lair" => if !self.happy_end {
+                        self.end = true;
+                    },
+                    "children" => {
+                        self.happy_end = true;
+                        self.end = true;
+                    }
+                    _ => (),
+                }
+            }
+            GameEvent::PlayerInDanger => {
+                self.textline.set_any_situation("danger", &mut self.rng);
+            }
+            GameEvent::PlayerMeetMonster => {
+                self.textscene.active = true;
+                self.textscene.scene = String::from("monster");
+                self.end = true;
+            }
+            GameEvent::MonsterStep { .. }
+            | GameEvent::MonsterBreath { .. }
+            | GameEvent::MonsterDied
+            | GameEvent::GameOver => (),
+        }
+
+        events.push(event);
+    }
+    //}}}
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::testing::messages_db;
+
+    #[test]
+    fn music_follows_the_game() {
+        let db_conn = messages_db(&[]);
+        let (map, _oil) = Map::from_ascii(
+            "\
+########
+#S_____#
+#______C
+########",
+        ).unwrap();
+        let mut game = GameState::with_map(
+            map,
+            GameRng::new(0),
+            &db_conn,
+            &Settings::default(),
+        ).unwrap();
+        assert_eq!(game.music_state(), MusicState::Exploring);
+
+        while game.player.get_view_distance() > 0 {
+            game.player.drain_view_resource();
+        }
+        assert_eq!(game.music_state(), MusicState::LampOut);
+
+        game.end = true;
+        assert_eq!(game.music_state(), MusicState::Ending);
+    }
+}
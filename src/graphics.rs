@@ -1,65 +1,46 @@
-use std::path::PathBuf;
 use std::collections::HashMap;
+use std::path::Path;
 
 use sdl2;
 use sdl2::Sdl;
 use sdl2::rwops::RWops;
-use sdl2::image::ImageRWops;
-use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::image::{ImageRWops, SaveSurface};
+use sdl2::surface::Surface;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
 use sdl2::rect::{Point, Rect};
 use sdl2::video::{Window, WindowPos};
-use sdl2::pixels::Color;
-use rusqlite::{Connection, DatabaseName, OpenFlags};
-use std::io::Read; // For Blob
-use rand::{thread_rng, Rng};
-
-use super::map::Map;
-use super::objects::Player;
-use super::get_setting;
-
-use super::DB_FILENAME;
-const DB_IMAGES_TABLE: &'static str = "images";
-const DB_IMAGES_COLUMN: &'static str = "image";
-const DB_FONTS_TABLE: &'static str = "fonts";
-const DB_FONTS_COLUMN: &'static str = "font";
+use sdl2::pixels::{Color, PixelFormatEnum};
+use rand::Rng;
+use rusqlite::Connection;
+
+use super::map::{Map, TileType};
+use super::objects::{Kobold, Player};
+use super::get_situations;
+use super::assets::{AssetKind, AssetSource};
+use super::GameRng;
+use super::settings::Settings;
+use super::save::{get_value, set_value};
+use super::{Error, Result};
+
 const DB_MESSAGES_TABLE: &'static str = "messages";
 
+// Darkness over the explored tiles out of the lamp light, 0 to 255
+const DIMMED_ALPHA: u8 = 160;
+
+// The kobold as it slips out of the light, drawn faded
+const KOBOLD_IMAGE: &'static str = "kobold.png";
+const KOBOLD_GLIMPSE: &'static str = "kobold.png glimpse";
+const GLIMPSE_ALPHA: u8 = 96;
+
 pub struct GUIElement {
     drawarea: Rect,
 }
 impl GUIElement {
-    pub fn init(name: &str) -> GUIElement {
-        let mut name_string: String = name.to_string() + "_";
-
-        name_string.push('x');
-        let x: i32 = match get_setting(&name_string) {
-            Some(value) => value,
-            None => 0,
-        };
-
-        name_string.pop();
-        name_string.push('y');
-        let y: i32 = match get_setting(&name_string) {
-            Some(value) => value,
-            None => 0,
-        };
-
-        name_string.pop();
-        name_string.push('w');
-        let width: u32 = match get_setting(&name_string) {
-            Some(value) => value,
-            None => 1,
-        };
-
-        name_string.pop();
-        name_string.push('h');
-        let height: u32 = match get_setting(&name_string) {
-            Some(value) => value,
-            None => 1,
-        };
+    pub fn init(name: &str, settings: &Settings) -> GUIElement {
+        let area = settings.area(name);
 
         GUIElement {
-            drawarea: Rect::new(x, y, width, height),
+            drawarea: Rect::new(area.x, area.y, area.w, area.h),
         }
     }
 
@@ -93,20 +74,36 @@ impl GUIElement {
 
 pub struct TextLine {
     situation: String,
+    situations: Vec<String>,
     time_max: u8,
     time: u8,
 }
 impl TextLine {
-    pub fn init() -> TextLine {
-        let time_max: u8 = match get_setting("textline_time_max") {
-            Some(value) => value,
-            None => 3,
-        };
-        TextLine {
+    pub fn init(settings: &Settings, db_conn: &Connection) -> Result<TextLine> {
+        let time_max: u8 = settings.textline_time_max;
+        Ok(TextLine {
             situation: String::from("start"),
+            situations: get_situations(db_conn, "")?,
             time_max,
             time: time_max,
-        }
+        })
+    }
+
+    pub fn save(&self, save_conn: &Connection) -> Result<()> {
+        set_value(save_conn, "textline_situation", &self.situation)?;
+        set_value(save_conn, "textline_time", self.time)?;
+        Ok(())
+    }
+
+    pub fn load(
+        save_conn: &Connection,
+        settings: &Settings,
+        db_conn: &Connection,
+    ) -> Result<TextLine> {
+        let mut textline = TextLine::init(settings, db_conn)?;
+        textline.situation = get_value(save_conn, "textline_situation")?;
+        textline.time = get_value(save_conn, "textline_time")?;
+        Ok(textline)
     }
 
     pub fn update(&mut self) {
@@ -120,30 +117,16 @@ impl TextLine {
         self.time = self.time_max;
     }
 
-    pub fn set_any_situation(&mut self, situation: &str) {
-        // Setting up database connection
-        let db_path: PathBuf = [".", DB_FILENAME].iter().collect();
-        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-        let db_connection = Connection::open_with_flags(&db_path, flags)
-            .expect("Cannot read data.");
-
-        let situation_in_q: String = String::from(situation) + "%";
-        let query: String = String::from("select situation ") + "from messages "
-            + "where situation like ?;";
-        let mut statement = db_connection.prepare(&query).unwrap();
-        let situations: Vec<String> = statement
-            .query_map(&[&situation_in_q], |row| {
-                let value: String = row.get(0);
-                value
-            })
-            .unwrap()
-            .map(|row| row.unwrap())
+    pub fn set_any_situation(&mut self, situation: &str, rng: &mut GameRng) {
+        let situations: Vec<&String> = self.situations
+            .iter()
+            .filter(|sit| sit.starts_with(situation))
             .collect();
 
-        let situation_sample: String = match thread_rng().choose(&situations) {
+        let situation_sample: String = match rng.choose(&situations) {
             Some(sit) => {
                 self.time = self.time_max;
-                sit.clone()
+                (*sit).clone()
             }
             None => String::from("empty"),
         };
@@ -186,16 +169,29 @@ pub struct TextScene {
     margin: i32,
 }
 impl TextScene {
-    pub fn init() -> TextScene {
+    pub fn init(settings: &Settings) -> TextScene {
         TextScene {
             active: false,
             scene: String::from("empty"),
-            margin: match get_setting("textscene_margin") {
-                Some(value) => value,
-                None => 10,
-            },
+            margin: settings.textscene_margin,
         }
     }
+
+    pub fn save(&self, save_conn: &Connection) -> Result<()> {
+        set_value(save_conn, "textscene_active", self.active)?;
+        set_value(save_conn, "textscene_scene", &self.scene)?;
+        Ok(())
+    }
+
+    pub fn load(
+        save_conn: &Connection,
+        settings: &Settings,
+    ) -> Result<TextScene> {
+        let mut textscene = TextScene::init(settings);
+        textscene.active = get_value(save_conn, "textscene_active")?;
+        textscene.scene = get_value(save_conn, "textscene_scene")?;
+        Ok(textscene)
+    }
 }
 
 pub trait Drawable {
@@ -212,13 +208,17 @@ impl Drawable for Map //{{{
         textures: &HashMap<String, Texture>,
         canvas: &mut Canvas<Window>,
     ) {
-        // Draw visible tiles
+        // Draw explored tiles, the ones out of the lamp light dimmed
         let texture_side: u32 = textures["wall.png"].query().width;
         let mut place: Rect = Rect::new(0, 0, texture_side, texture_side);
+        let previous_color: Color = canvas.draw_color();
+        let previous_blend: BlendMode = canvas.blend_mode();
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, DIMMED_ALPHA));
 
         for (x, column) in self.tiles.iter().enumerate() {
             for (y, tile) in column.iter().enumerate() {
-                if tile.visible {
+                if tile.explored {
                     let texture: &Texture = &textures[&tile.icon];
                     let tx = ((x as u32) * texture_side) as i32;
                     let ty = ((y as u32) * texture_side) as i32;
@@ -228,10 +228,18 @@ impl Drawable for Map //{{{
                     canvas
                         .copy(texture, None, place)
                         .expect("Texture rendering error!");
+                    if !tile.lit {
+                        canvas
+                            .fill_rect(place)
+                            .expect("Dimming rendering error!");
+                    }
                 }
             }
         }
 
+        canvas.set_blend_mode(previous_blend);
+        canvas.set_draw_color(previous_color);
+
         // Draw player marks
         let mark_texture: &Texture = &textures["mark.png"];
         for mark in self.marks.iter() {
@@ -268,6 +276,40 @@ impl Drawable for Player //{{{
     }
 }
 //}}}
+impl Drawable for Kobold //{{{
+{
+    /*
+     * Only when the kobold is in the light, or has just left it.
+     * Databases without the kobold image keep it unseen.
+     */
+    fn draw(
+        &self,
+        textures: &HashMap<String, Texture>,
+        canvas: &mut Canvas<Window>,
+    ) {
+        let (texture_name, (x, y)) = match (self.seen_at(), self.glimpse()) {
+            (Some(location), _) => (KOBOLD_IMAGE, location),
+            (None, Some(location)) => (KOBOLD_GLIMPSE, location),
+            (None, None) => return,
+        };
+        let texture: &Texture = match textures.get(texture_name) {
+            Some(texture) => texture,
+            None => return,
+        };
+
+        let texture_side: u32 = texture.query().width;
+        let place: Rect = Rect::new(
+            ((x as u32) * texture_side) as i32,
+            ((y as u32) * texture_side) as i32,
+            texture_side,
+            texture_side,
+        );
+        canvas
+            .copy(texture, None, place)
+            .expect("Texture rendering error!");
+    }
+}
+//}}}
 impl Drawable for TextLine //{{{
 {
     fn draw(
@@ -419,229 +461,336 @@ impl Drawable for TextScene //{{{
 /*
  * This function initializes textures for further usage by *draw* functions.
  */
-pub fn init_textures<T>(
-    texture_creator: &TextureCreator<T>,
-) -> HashMap<String, Texture> {
+pub fn init_textures<'a, T>(
+    texture_creator: &'a TextureCreator<T>,
+    assets: &AssetSource,
+    db_connection: &Connection,
+    settings: &Settings,
+) -> Result<HashMap<String, Texture<'a>>> {
     let mut textures: HashMap<String, Texture> = HashMap::new();
 
-    // Setting up database connection
-    let db_path: PathBuf = [".", DB_FILENAME].iter().collect();
-    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-    let db_connection = Connection::open_with_flags(&db_path, flags)
-        .expect("Cannot read data.");
-
     //{{{ Pictures
-    // Query for retrieving images location in DB
-    let query =
-        String::from("select name, rowid from ") + DB_IMAGES_TABLE + ";";
-    let mut statement = db_connection
-        .prepare(&query)
-        .expect("Cannot prepary query.");
+    let images_table = AssetKind::Image.table();
+    for image in assets.load(AssetKind::Image)? {
+        let abstract_stream: RWops = RWops::from_bytes(&image.bytes)
+            .map_err(|_| Error::corrupt_blob(images_table, &image.name))?;
 
-    for maybe_row_content in statement
-        .query_map(&[], |row| {
-            let name: String = row.get(0);
-            let id: i64 = row.get(1);
-            (id, name)
-        })
-        .unwrap()
-    {
-        if let Ok((id, name)) = maybe_row_content {
-            // Getting image
-            let mut image_blob = db_connection
-                .blob_open(
-                    DatabaseName::Main,
-                    DB_IMAGES_TABLE,
-                    DB_IMAGES_COLUMN,
-                    id,
-                    true, // Read-Only
-                )
-                .expect("Cannot read image blob.");
-
-            let mut image_bytes: Vec<u8> = Vec::new();
-            image_blob
-                .read_to_end(&mut image_bytes)
-                .expect("Cannot read image bytes.");
-
-            let abstract_stream: RWops = RWops::from_bytes(&image_bytes)
-                .expect("Cannot open image bytes as a stream.");
-
-            let image_surface = abstract_stream
-                .load()
-                .expect("Cannot create surface from image stream.");
-
-            let texture = texture_creator
-                .create_texture_from_surface(&image_surface)
-                .expect("Cannot create texture from image surface.");
+        let image_surface = abstract_stream
+            .load()
+            .map_err(|_| Error::corrupt_blob(images_table, &image.name))?;
+
+        let texture = texture_creator
+            .create_texture_from_surface(&image_surface)
+            .map_err(|e| Error::sdl("render", e))?;
 
-            textures.insert(name, texture);
+        if image.name == KOBOLD_IMAGE {
+            let mut glimpse = texture_creator
+                .create_texture_from_surface(&image_surface)
+                .map_err(|e| Error::sdl("render", e))?;
+            glimpse.set_alpha_mod(GLIMPSE_ALPHA);
+            textures.insert(String::from(KOBOLD_GLIMPSE), glimpse);
         }
+
+        textures.insert(image.name, texture);
     }
     //}}}
 
     //{{{ Messages
     // Initializing SDL TTF
-    let sdl_ttf = sdl2::ttf::init().expect("SDL TTF initialization error.");
-
-    // Getting font from database
-    let mut font_blob = db_connection
-        .blob_open(
-            DatabaseName::Main,
-            DB_FONTS_TABLE,
-            DB_FONTS_COLUMN,
-            1,
-            true, // Read-Only
-        )
-        .expect("Cannot read font blob.");
-
-    let mut font_bytes: Vec<u8> = Vec::new();
-    font_blob
-        .read_to_end(&mut font_bytes)
-        .expect("Cannot read font bytes.");
+    let sdl_ttf = sdl2::ttf::init().map_err(|e| Error::sdl("ttf", e))?;
 
-    let font_height: u16 = match get_setting("textline_font_size") {
-        Some(height) => height,
-        None => 12,
-    };
-    let font_height_end: u16 = match get_setting("endings_font_size") {
-        Some(height) => height,
-        None => 18,
+    // Getting font from the assets
+    let font_asset = assets.load_first(AssetKind::Font)?;
+    let bad_font = |_: String| {
+        Error::corrupt_blob(AssetKind::Font.table(), &font_asset.name)
     };
 
+    let font_height: u16 = settings.textline_font_size;
+    let font_height_end: u16 = settings.endings_font_size;
+
     let font = sdl_ttf
         .load_font_from_rwops(
-            RWops::from_bytes(&font_bytes)
-                .expect("Cannot open font bytes as a stream."),
+            RWops::from_bytes(&font_asset.bytes).map_err(&bad_font)?,
             font_height,
         )
-        .expect("Cannot load font from a stream.");
+        .map_err(&bad_font)?;
     let font_end = sdl_ttf
         .load_font_from_rwops(
-            RWops::from_bytes(&font_bytes)
-                .expect("Cannot open font bytes as a stream."),
+            RWops::from_bytes(&font_asset.bytes).map_err(&bad_font)?,
             font_height_end,
         )
-        .expect("Cannot load font from a stream.");
+        .map_err(&bad_font)?;
 
     // Rendering messages with the selected font
-    let max_line_width: u32 = match get_setting("textline_max_width") {
-        Some(value) => value,
-        None => 100,
-    };
+    let max_line_width: u32 = settings.textline_max_width;
 
     let query = String::from("select * from ") + DB_MESSAGES_TABLE + ";";
     let mut statement = db_connection
         .prepare(&query)
-        .expect("Cannot prepary query.");
-
-    for maybe_row_content in statement
-        .query_map(&[], |row| {
-            let situation: String = row.get(0);
-            let message: String = row.get(1);
-            (situation, message)
-        })
-        .unwrap()
-    {
-        if let Ok((situation, message)) = maybe_row_content {
-            // Rendering message
-            let text_surface = font.render(&message)
-                .blended_wrapped(Color::RGB(0, 0, 0), max_line_width)
-                .expect("Cannot create text surface.");
-            let text_texture = texture_creator
-                .create_texture_from_surface(text_surface)
-                .expect("Cannot render text.");
-
-            textures.insert(situation, text_texture);
-        }
+        .map_err(|_| Error::MissingTable(String::from(DB_MESSAGES_TABLE)))?;
+
+    for maybe_row_content in statement.query_map(&[], |row| {
+        let situation: String = row.get(0);
+        let message: String = row.get(1);
+        (situation, message)
+    })? {
+        let (situation, message) = maybe_row_content?;
+
+        // Rendering message
+        let text_surface = font.render(&message)
+            .blended_wrapped(Color::RGB(0, 0, 0), max_line_width)
+            .map_err(|e| Error::sdl("ttf", e))?;
+        let text_texture = texture_creator
+            .create_texture_from_surface(text_surface)
+            .map_err(|e| Error::sdl("render", e))?;
+
+        textures.insert(situation, text_texture);
     }
 
     // Rendering scene messages with the selected font
-    let max_line_width: u32 = match get_setting("textscene_max_width") {
-        Some(value) => value,
-        None => 100,
-    };
+    let max_line_width: u32 = settings.textscene_max_width;
 
     let query = String::from("select * from scenes;");
     let mut statement = db_connection
         .prepare(&query)
-        .expect("Cannot prepary query.");
-
-    for maybe_row_content in statement
-        .query_map(&[], |row| {
-            let scene: String = row.get(0);
-            let text: String = row.get(1);
-            (scene, text)
-        })
-        .unwrap()
-    {
-        if let Ok((scene, text)) = maybe_row_content {
-            // Rendering message
-            let current_font = if scene.starts_with("end_") {
-                &font_end
-            } else {
-                &font
-            };
-            let text_surface = current_font
-                .render(&text)
-                .blended_wrapped(Color::RGB(0, 0, 0), max_line_width)
-                .expect("Cannot create text surface.");
-            let text_texture = texture_creator
-                .create_texture_from_surface(text_surface)
-                .expect("Cannot render text.");
-
-            textures.insert(scene, text_texture);
-        }
+        .map_err(|_| Error::MissingTable(String::from("scenes")))?;
+
+    for maybe_row_content in statement.query_map(&[], |row| {
+        let scene: String = row.get(0);
+        let text: String = row.get(1);
+        (scene, text)
+    })? {
+        let (scene, text) = maybe_row_content?;
+
+        // Rendering message
+        let current_font = if scene.starts_with("end_") {
+            &font_end
+        } else {
+            &font
+        };
+        let text_surface = current_font
+            .render(&text)
+            .blended_wrapped(Color::RGB(0, 0, 0), max_line_width)
+            .map_err(|e| Error::sdl("ttf", e))?;
+        let text_texture = texture_creator
+            .create_texture_from_surface(text_surface)
+            .map_err(|e| Error::sdl("render", e))?;
+
+        textures.insert(scene, text_texture);
     }
     //}}}
 
-    textures
+    Ok(textures)
+}
+//}}}
+
+//{{{ WindowMode
+/*
+ * How the window is shown, chosen from the command line
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WindowMode {
+    pub fullscreen: bool,
+    // Window size relative to the background image
+    pub scale: f32,
+}
+impl Default for WindowMode {
+    fn default() -> WindowMode {
+        WindowMode {
+            fullscreen: false,
+            scale: 1.0,
+        }
+    }
 }
 //}}}
 
 /*
  * This function initializes SDL2 window
  */
-pub fn init(sdl_context: &Sdl) -> Canvas<Window> {
-    let game_name: String = match get_setting("game_name") {
-        Some(name) => name,
-        None => String::from("Debug"),
-    };
+pub fn init(
+    sdl_context: &Sdl,
+    settings: &Settings,
+    mode: WindowMode,
+) -> Result<Canvas<Window>> {
+    let game_name: &str = &settings.game_name;
 
     // Init SDL2 and it's subsystems
-    let sdl_video = sdl_context
-        .video()
-        .expect("SDL video subsystem initialization error.");
+    let sdl_video = sdl_context.video().map_err(|e| Error::sdl("video", e))?;
     let _sdl_image = sdl2::image::init(sdl2::image::INIT_PNG)
-        .expect("SDL Image initialization error.");
+        .map_err(|e| Error::sdl("image", e))?;
 
     // Init main window
-    let window = sdl_video
-        .window(&game_name, 10, 10)
+    let mut window_builder = sdl_video.window(game_name, 10, 10);
+    if mode.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder
         .build()
-        .expect("Window build error.");
+        .map_err(|e| Error::sdl("video", e))?;
     let mut canvas = window
         .into_canvas()
         .build()
-        .expect("Canvas creation error.");
+        .map_err(|e| Error::sdl("render", e))?;
 
     // Set background canvas
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
     canvas.present();
 
-    canvas
+    Ok(canvas)
 }
 
 pub fn configure_window(
-    window: &mut Window,
+    canvas: &mut Canvas<Window>,
     textures: &HashMap<String, Texture>,
-) {
-    let bg_texture: &Texture = &textures["map.png"];
-    let window_width: u32 = bg_texture.query().width;
-    let window_height: u32 = bg_texture.query().height;
+    mode: WindowMode,
+) -> Result<()> {
+    let bg_texture: &Texture = textures.get("map.png").ok_or_else(|| {
+        Error::missing_row(AssetKind::Image.table(), "map.png")
+    })?;
+    let game_width: u32 = bg_texture.query().width;
+    let game_height: u32 = bg_texture.query().height;
+
+    // Everything is drawn in background image coordinates,
+    // SDL stretches it to the window and translates mouse clicks back
+    canvas
+        .set_logical_size(game_width, game_height)
+        .map_err(|e| Error::sdl("render", e))?;
+
+    let window_width = (game_width as f32 * mode.scale).round() as u32;
+    let window_height = (game_height as f32 * mode.scale).round() as u32;
 
+    let window = canvas.window_mut();
     window
-        .set_size(window_width, window_height)
-        .expect("Window resizing error.");
+        .set_size(window_width.max(1), window_height.max(1))
+        .map_err(|e| Error::sdl("video", e))?;
     window.set_position(WindowPos::Centered, WindowPos::Centered);
+
+    Ok(())
+}
+
+/*
+ * The map is drawn one tile texture per tile from the corner of its
+ * area, so a bigger field needs a bigger `map_w` and `map_h`
+ */
+pub fn check_map_area(
+    map: &Map,
+    textures: &HashMap<String, Texture>,
+    settings: &Settings,
+) -> Result<()> {
+    let tile_side = match textures.get("wall.png") {
+        Some(texture) => texture.query().width as usize,
+        None => {
+            return Err(Error::missing_row(AssetKind::Image.table(), "wall.png"))
+        }
+    };
+    let area = settings.area("map");
+
+    for &(setting, tiles, room) in [
+        ("field_width", map.width, area.w),
+        ("field_height", map.height, area.h),
+    ].iter()
+    {
+        if tiles * tile_side > room as usize {
+            return Err(Error::InvalidSetting {
+                setting: String::from(setting),
+                reason: format!(
+                    "{} tiles of {} pixels do not fit into {} pixels \
+                     of the map area",
+                    tiles, tile_side, room
+                ),
+            });
+        }
+    }
+
+    Ok(())
 }
+
+//{{{ export_png
+/*
+ * Picture of the whole map made of the tile images, for reviewing seeds.
+ * Obstacles and curios look like floor in the game,
+ * so they are marked with colored squares here.
+ */
+pub fn export_png(
+    map: &Map,
+    oil: &[(usize, usize)],
+    assets: &AssetSource,
+    png_path: &Path,
+) -> Result<()> {
+    let _sdl_image = sdl2::image::init(sdl2::image::INIT_PNG)
+        .map_err(|e| Error::sdl("image", e))?;
+
+    let format = Surface::new(1, 1, PixelFormatEnum::RGBA8888)
+        .map_err(|e| Error::sdl("surface", e))?
+        .pixel_format();
+    let images_table = AssetKind::Image.table();
+    let mut images: HashMap<String, Surface> = HashMap::new();
+    for image in assets.load(AssetKind::Image)? {
+        let surface = RWops::from_bytes(&image.bytes)
+            .and_then(|stream| stream.load().and_then(|s| s.convert(&format)))
+            .map_err(|_| Error::corrupt_blob(images_table, &image.name))?;
+        images.insert(image.name, surface);
+    }
+    let image = |name: &str| {
+        images
+            .get(name)
+            .ok_or_else(|| Error::missing_row(images_table, name))
+    };
+
+    let side: u32 = image("floor.png")?.width();
+    let (width, height) = (map.width as u32, map.height as u32);
+    let mut picture =
+        Surface::new(width * side, height * side, PixelFormatEnum::RGBA8888)
+            .map_err(|e| Error::sdl("surface", e))?;
+
+    let start = map.get_location("start");
+    for (x, column) in map.tiles.iter().enumerate() {
+        for (y, tile) in column.iter().enumerate() {
+            let place = Rect::new(
+                (x as u32 * side) as i32,
+                (y as u32 * side) as i32,
+                side,
+                side,
+            );
+            image(&tile.icon)?
+                .blit_scaled(None, &mut picture, place)
+                .map_err(|e| Error::sdl("surface", e))?;
+
+            let marker = match tile.ttype {
+                TileType::Obstacle => Some(Color::RGB(160, 40, 40)),
+                TileType::Curiosity => Some(Color::RGB(220, 180, 40)),
+                _ => None,
+            };
+            if let Some(color) = marker {
+                let inner = Rect::new(
+                    place.x() + (side / 4) as i32,
+                    place.y() + (side / 4) as i32,
+                    side / 2,
+                    side / 2,
+                );
+                picture
+                    .fill_rect(inner, color)
+                    .map_err(|e| Error::sdl("surface", e))?;
+            }
+
+            let icon = if start == Some((x, y)) {
+                Some("player.png")
+            } else if oil.contains(&(x, y)) {
+                Some("flask.png")
+            } else {
+                None
+            };
+            if let Some(icon) = icon {
+                image(icon)?
+                    .blit_scaled(None, &mut picture, place)
+                    .map_err(|e| Error::sdl("surface", e))?;
+            }
+        }
+    }
+
+    picture
+        .save(png_path)
+        .map_err(|e| Error::sdl("image", e))
+}
+//}}}
@@ -1,5 +1,6 @@
 --{{{ Tables
-create table cards (tiles BLOB);
+-- fixed: 1 keeps the card as drawn, without rotations and flips
+create table cards (name TEXT, tiles BLOB, fixed INTEGER DEFAULT 0);
 create table images(name TEXT, image BLOB);
 create table fonts(name TEXT, font BLOB);
 create table messages(situation TEXT, message TEXT);
@@ -45,7 +46,20 @@ insert into game_settings(setting, value) values
     ('visible_distance',      3),
     ('resource_max',         10),
     ('resource_start',      100),
-    ('obstacle_max',          8);
+    ('obstacle_max',          8),
+    ('field_width',           3),
+    ('field_height',          3),
+    ('kobold_hear_dist',      2),
+    ('kobold_light_dist',     3),
+    ('kobold_track_turns',    6),
+    ('kobold_wander_dist',    4),
+    ('music_exploring', 'la_femme.mp3'),
+    ('music_danger',    'la_femme.mp3'),
+    ('music_lamp_out',  'la_femme.mp3'),
+    ('music_ending',    'la_femme.mp3'),
+    ('music_fade_ms',      1000),
+    ('sound_pitch_jitter',    6),
+    ('sound_volume_jitter',  20);
 insert into fonts(name, font) values
     ('DejaVu Serif', readfile('data/DejaVuSerif.ttf'));
 insert into images(name, image) values
@@ -53,6 +67,7 @@ insert into images(name, image) values
     ('mark.png',     readfile('data/tiles/mark.png')  ),
     ('floor.png',    readfile('data/tiles/floor.png') ),
     ('player.png',   readfile('data/icons/player.png')),
+    ('kobold.png',   readfile('data/icons/kobold.png')),
     ('flask.png',    readfile('data/icons/flask.png') ),
     ('map.png',      readfile('data/map.png')         ),
     ('scene_bg.png', readfile('data/scene_bg.png')    ),
@@ -60,9 +75,13 @@ insert into images(name, image) values
 insert into musics(name, music) values
     ('la_femme.mp3', readfile('data/sounds/la_femme.mp3'));
 insert into sound_effects(name, effect) values
-    ('match.wav',   readfile('data/sounds/match_out.wav')    ),
-    ('fizzing.wav', readfile('data/sounds/fizzing.wav')      ),
-    ('shout.wav',   readfile('data/sounds/monster_shout.wav'));
+    ('resource_refill.wav', readfile('data/sounds/match_out.wav')     ),
+    ('resource_gone.wav',   readfile('data/sounds/fizzing.wav')       ),
+    ('monster_died.wav',    readfile('data/sounds/monster_shout.wav') ),
+    ('monster_step1.wav',   readfile('data/sounds/kobold_step.wav')   ),
+    ('monster_step2.wav',   readfile('data/sounds/kobold_step2.wav')  ),
+    ('monster_breath1.wav', readfile('data/sounds/kobold_breath.wav') ),
+    ('monster_breath2.wav', readfile('data/sounds/kobold_breath2.wav'));
 insert into scenes(scene, message) values
     ('body', 'Oh no… I heard about this from my grandfather. Decades ago this mine was closed because of evil spirit killing miners. Wait, what is that? A note in his hand. «Seek the cursed item. Put a rusty needle out from the rotten heart to free him from his curse.» Looks like he sought something before he died in this cave in. Rest in peace, dead miner.'),
     ('rest', 'A dead end. But I can see the sun through the cracks above. How much time have passed since I entered this forgotten mines?.. My legs hurt and I am starving a little. But it is even worse for children. I must find them before dark. Grandfather told me some stories about this mines… Yes, I think I know where to seek my grandchildren.'),
@@ -97,25 +116,25 @@ insert into messages(situation, message) values
     ('enter_close', 'Press [Enter] to close'),
     ('resource_keys', '[R][0]'),
     ('empty', ' ');
-insert into cards(tiles) values
-    (readfile('data/cards/field1') ),
-    (readfile('data/cards/field2') ),
-    (readfile('data/cards/field3') ),
-    (readfile('data/cards/field4') ),
-    (readfile('data/cards/field5') ),
-    (readfile('data/cards/field6') ),
-    (readfile('data/cards/field7') ),
-    (readfile('data/cards/field8') ),
-    (readfile('data/cards/field9') ),
-    (readfile('data/cards/field10')),
-    (readfile('data/cards/field11')),
-    (readfile('data/cards/field12')),
-    (readfile('data/cards/field13')),
-    (readfile('data/cards/field14')),
-    (readfile('data/cards/field15')),
-    (readfile('data/cards/field16')),
-    (readfile('data/cards/field17')),
-    (readfile('data/cards/field18'));
+insert into cards(name, tiles) values
+    ('field1',  readfile('data/cards/field1')),
+    ('field2',  readfile('data/cards/field2')),
+    ('field3',  readfile('data/cards/field3')),
+    ('field4',  readfile('data/cards/field4')),
+    ('field5',  readfile('data/cards/field5')),
+    ('field6',  readfile('data/cards/field6')),
+    ('field7',  readfile('data/cards/field7')),
+    ('field8',  readfile('data/cards/field8')),
+    ('field9',  readfile('data/cards/field9')),
+    ('field10', readfile('data/cards/field10')),
+    ('field11', readfile('data/cards/field11')),
+    ('field12', readfile('data/cards/field12')),
+    ('field13', readfile('data/cards/field13')),
+    ('field14', readfile('data/cards/field14')),
+    ('field15', readfile('data/cards/field15')),
+    ('field16', readfile('data/cards/field16')),
+    ('field17', readfile('data/cards/field17')),
+    ('field18', readfile('data/cards/field18'));
 --insert into tile_images(name, image) values
     --('filename.filetype', readfile('data/img/filepath')),
     --('filename.filetype', readfile('data/img/filepath'))
@@ -3,31 +3,80 @@ extern crate rand;
 extern crate rusqlite;
 extern crate sdl2;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
-use rusqlite::{Connection, OpenFlags};
-use rusqlite::types::FromSql;
+use rand::{Rng, SeedableRng, StdRng};
+use std::io::Read; // For Blob
+use rusqlite::{Connection, DatabaseName, OpenFlags};
 
 pub const DB_FILENAME: &'static str = "data.sqlite3";
 
-pub fn get_setting<T: FromSql>(setting_name: &str) -> Option<T> {
-    // Setting up database connection
-    let db_path: PathBuf = [".", DB_FILENAME].iter().collect();
+pub fn open_database() -> Result<Connection> {
+    open_database_at(&database_path(None))
+}
+
+/*
+ * Every translation has its own database: `data.sqlite3` is English,
+ * `data_ru.sqlite3` is Russian and so on.
+ */
+pub fn database_path(lang: Option<&str>) -> PathBuf {
+    let file_name = match lang {
+        None | Some("en") => String::from(DB_FILENAME),
+        Some(lang) => DB_FILENAME.replacen(".", &format!("_{}.", lang), 1),
+    };
+    [".", &file_name].iter().collect()
+}
+
+pub fn open_database_at(db_path: &Path) -> Result<Connection> {
     let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-    let db_connection = Connection::open_with_flags(&db_path, flags)
-        .expect("Cannot read data.");
-
-    match db_connection.query_row(
-        "select value from game_settings where setting like ?;",
-        &[&setting_name],
-        |row| {
-            let value: T = row.get(0);
-            value
-        },
-    ) {
-        Ok(v) => Some(v),
-        Err(_) => None,
-    }
+    Connection::open_with_flags(db_path, flags)
+        .map_err(|_| Error::MissingDatabase(db_path.to_path_buf()))
+}
+
+pub fn read_blob(
+    db_conn: &Connection,
+    table: &str,
+    column: &str,
+    rowid: i64,
+) -> Result<Vec<u8>> {
+    let mut blob = db_conn
+        .blob_open(
+            DatabaseName::Main,
+            table,
+            column,
+            rowid,
+            true, // Read-Only
+        )
+        .map_err(|_| Error::missing_row(table, rowid))?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    blob.read_to_end(&mut bytes)
+        .map_err(|_| Error::corrupt_blob(table, rowid))?;
+
+    Ok(bytes)
+}
+
+/*
+ * Names of all message situations starting with `prefix`
+ */
+pub fn get_situations(
+    db_conn: &Connection,
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let query = "select situation from messages where situation like ?;";
+    let mut statement = db_conn
+        .prepare(query)
+        .map_err(|_| Error::MissingTable(String::from("messages")))?;
+
+    let pattern: String = String::from(prefix) + "%";
+    let situations: Vec<String> = statement
+        .query_map(&[&pattern], |row| {
+            let situation: String = row.get(0);
+            situation
+        })?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    Ok(situations)
 }
 
 pub fn generate_seed() -> usize {
@@ -41,15 +90,164 @@ pub fn generate_seed() -> usize {
     }
 }
 
+/*
+ * The only source of randomness for the game.
+ * Same seed gives the same map, obstacles, curios, oil and kobold walk.
+ */
+pub struct GameRng {
+    seed: usize,
+    rng: StdRng,
+    // Numbers taken so far, enough to restore the state from the seed
+    draws: u64,
+}
+impl GameRng {
+    pub fn new(seed: usize) -> GameRng {
+        let seed_slice: &[_] = &[seed];
+        GameRng {
+            seed,
+            rng: SeedableRng::from_seed(seed_slice),
+            draws: 0,
+        }
+    }
+
+    /*
+     * Same state as the one which took `draws` numbers after `new(seed)`
+     */
+    pub fn resume(seed: usize, draws: u64) -> GameRng {
+        let mut rng = GameRng::new(seed);
+        for _ in 0..draws {
+            rng.next_u64();
+        }
+        rng
+    }
+
+    pub fn seed(&self) -> usize {
+        self.seed
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+// Every number comes from next_u64, the way StdRng makes them,
+// so one draw is one step of the stream whatever its size
+impl Rng for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let mut number = self.next_u64();
+            for byte in chunk.iter_mut() {
+                *byte = number as u8;
+                number >>= 8;
+            }
+        }
+    }
+}
+
+pub mod error;
+pub use error::{Error, Result};
+
+pub mod settings;
+pub mod assets;
 pub mod objects;
+pub mod fov;
 pub mod map;
 pub mod graphics;
 pub mod sound;
+pub mod events;
+pub mod game;
+pub mod save;
+pub mod preferences;
+pub mod replay;
+#[cfg(test)]
+mod testing;
 
 #[cfg(test)]
 mod tests {
+    use rand::Rng;
+    use super::{database_path, GameRng};
+    use super::assets::DirectoryAssets;
+    use super::game::{Command, GameState};
+    use super::settings::Settings;
+    use super::testing::messages_db;
+    use std::path::Path;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn database_per_language() {
+        assert_eq!(database_path(None), Path::new("./data.sqlite3"));
+        assert_eq!(database_path(Some("en")), Path::new("./data.sqlite3"));
+        assert_eq!(database_path(Some("ru")), Path::new("./data_ru.sqlite3"));
+    }
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut first = GameRng::new(42);
+        let mut second = GameRng::new(42);
+        let first_run: Vec<u32> = (0..16).map(|_| first.gen()).collect();
+        let second_run: Vec<u32> = (0..16).map(|_| second.gen()).collect();
+
+        assert_eq!(first_run, second_run);
+        assert_eq!(first.seed(), 42);
+
+        let mut resumed = GameRng::resume(42, first.draws());
+        assert_eq!(resumed.gen::<u32>(), first.gen::<u32>());
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let db_conn = messages_db(&[
+            ("obstacle1", "A crate"),
+            ("obstacle2", "A barrel"),
+            ("obstacle3", "A cart"),
+            ("resource_found1", "Oil"),
+            ("resource_found2", "More oil"),
+            ("danger1", "Steps"),
+            ("danger2", "Breath"),
+        ]);
+        let assets = DirectoryAssets::new("data");
+        let settings = Settings::default();
+
+        let run = || {
+            let mut game =
+                GameState::init_with_seed(44, &assets, &db_conn, &settings)
+                    .unwrap();
+            // Obstacle texts and curio scenes
+            let texts: Vec<String> = game.map
+                .tiles
+                .iter()
+                .flat_map(|column| column.iter())
+                .map(|tile| tile.search_text.clone())
+                .collect();
+            let mut events = Vec::new();
+            for _ in 0..10 {
+                for &(x_mod, y_mod) in [(1, 0), (1, 1), (0, 1), (-1, 0)].iter()
+                {
+                    events.extend(game.apply(Command::Move(x_mod, y_mod)));
+                }
+                events.extend(game.apply(Command::Refill));
+            }
+            (
+                game.map.to_ascii(game.resources.locations()),
+                texts,
+                game.resources.locations().to_vec(),
+                (game.monster.x, game.monster.y),
+                events,
+            )
+        };
+
+        assert_eq!(run(), run());
+    }
 }
@@ -1,177 +1,344 @@
-extern crate rusqlite;
 extern crate sdl2;
 extern crate sevend;
 
-use std::time::Duration;
+use std::env;
 use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
 
 use sdl2::event::Event;
-use sdl2::keyboard::{Keycode, Scancode, NOMOD};
+use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseButton;
-use sdl2::mixer::{DEFAULT_CHANNELS, DEFAULT_FORMAT};
-use sdl2::mixer::INIT_MP3;
-use sdl2::rwops::RWops;
-use sdl2::mixer::LoaderRWops;
-use rusqlite::{Connection, DatabaseName, OpenFlags};
-use std::io::Read; // For Blob
-
-use sevend::map::Map;
-use sevend::objects::{Kobold, Player, Resources};
-use sevend::objects::EventResourceFound;
-use sevend::objects::EventResourceGone;
-use sevend::objects::EventResourceRefill;
-use sevend::objects::EventObstacleFound;
-use sevend::objects::EventCurioFound;
-use sevend::objects::EventPlayerInDanger;
-use sevend::objects::EventPlayerMeetMonster;
+
+use sevend::events::GameEvent;
+use sevend::game::{Command, GameState};
 use sevend::graphics;
 use sevend::graphics::GUIElement;
-use sevend::graphics::{Background, ResourceCounter, TextLine, TextScene};
-use sevend::graphics::{configure_window, init_textures};
+use sevend::graphics::{Background, ResourceCounter};
+use sevend::graphics::{configure_window, init_textures, WindowMode};
+use sevend::settings::Settings;
 use sevend::sound;
+use sevend::sound::AudioBackend;
+use sevend::save;
+use sevend::preferences;
+use sevend::preferences::{Preferences, Volume};
+use sevend::replay::{Playback, Replay};
+use sevend::assets::{AssetSource, DirectoryAssets, SqliteAssets};
+use sevend::{database_path, open_database_at};
+use sevend::{Error, Result};
+
+const USAGE: &'static str = "Usage: kobold [options]
+
+Options:
+    --seed <number>   start a new game with the map from this seed
+    --save <path>     keep the unfinished game in this file; games of
+                      --seed and --record are only kept with it
+    --record <path>   start a new game and write its replay to this file
+    --replay <path>   watch a recorded game: space pauses it,
+                      right arrow takes a single step while paused
+    --db <path>       read game data from this database
+    --data <dir>      read images, sounds and cards from the files of
+                      this directory, like data/, instead of the database
+    --lang <code>     read game data from data_<code>.sqlite3
+    --fullscreen      fill the whole screen
+    --scale <factor>  window size relative to the game, 1 by default
+    --no-audio        play neither music nor sounds
+    --help            show this message";
+
+// Time between the commands of a watched replay
+const REPLAY_STEP: Duration = Duration::from_millis(250);
+
+//{{{ Options
+#[derive(Debug, PartialEq)]
+struct Options {
+    seed: Option<usize>,
+    db_path: PathBuf,
+    data_dir: Option<PathBuf>,
+    save_path: Option<PathBuf>,
+    record_path: Option<PathBuf>,
+    replay_path: Option<PathBuf>,
+    window_mode: WindowMode,
+    audio: bool,
+    help: bool,
+}
 
-use sevend::DB_FILENAME;
-
-fn main() {
-    // Initializing SDL2 variables
-    let sdl_context = sdl2::init().expect("SDL initialization error.");
+fn parse_options<I>(args: I) -> ::std::result::Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut seed: Option<usize> = None;
+    let mut db_path: Option<PathBuf> = None;
+    let mut data_dir: Option<PathBuf> = None;
+    let mut save_path: Option<PathBuf> = None;
+    let mut record_path: Option<PathBuf> = None;
+    let mut replay_path: Option<PathBuf> = None;
+    let mut lang: Option<String> = None;
+    let mut window_mode = WindowMode::default();
+    let mut audio = true;
+    let mut help = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.as_str() {
+            "--seed" => {
+                let text = value("--seed")?;
+                let number = text
+                    .parse::<usize>()
+                    .map_err(|_| format!("Seed {} is not a number", text))?;
+                seed = Some(number);
+            }
+            "--db" => db_path = Some(PathBuf::from(value("--db")?)),
+            "--data" => data_dir = Some(PathBuf::from(value("--data")?)),
+            "--save" => save_path = Some(PathBuf::from(value("--save")?)),
+            "--record" => {
+                record_path = Some(PathBuf::from(value("--record")?))
+            }
+            "--replay" => {
+                replay_path = Some(PathBuf::from(value("--replay")?))
+            }
+            "--lang" => lang = Some(value("--lang")?),
+            "--fullscreen" => window_mode.fullscreen = true,
+            "--scale" => {
+                let text = value("--scale")?;
+                match text.parse::<f32>() {
+                    Ok(scale) if scale > 0.0 && scale.is_finite() => {
+                        window_mode.scale = scale;
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Scale {} is not a positive number",
+                            text
+                        ))
+                    }
+                }
+            }
+            "--no-audio" => audio = false,
+            "--help" | "-h" => help = true,
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if replay_path.is_some() && (record_path.is_some() || seed.is_some()) {
+        return Err(String::from(
+            "--replay has its own seed and cannot be recorded",
+        ));
+    }
 
-    // Init textures
-    let mut canvas = graphics::init(&sdl_context);
-    let texture_creator = canvas.texture_creator();
-    let textures = init_textures(&texture_creator);
+    // Games started by --seed or --record do not replace
+    // the unfinished one unless told where to go
+    let save_path = match save_path {
+        Some(path) => Some(path),
+        None if seed.is_none() && record_path.is_none() => {
+            Some(save::save_path())
+        }
+        None => None,
+    };
+
+    Ok(Options {
+        seed,
+        // Explicit database wins over the language one
+        db_path: db_path.unwrap_or_else(|| {
+            database_path(lang.as_ref().map(|lang| lang.as_str()))
+        }),
+        data_dir,
+        save_path,
+        record_path,
+        replay_path,
+        window_mode,
+        audio,
+        help,
+    })
+}
+//}}}
 
-    // Init sounds
-    let _sdl_audio = sdl_context
-        .audio()
-        .expect("SDL AudioSubsystem initialization failed");
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(reason) => {
+            eprintln!("{}\n\n{}", reason, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
 
-    let chunk_size = 1_024;
-    let frequency = 44_100;
-    sdl2::mixer::open_audio(
-        frequency,
-        DEFAULT_FORMAT,
-        DEFAULT_CHANNELS,
-        chunk_size,
-    ).expect("Failed to open audio device");
+    if let Err(error) = run(&options) {
+        eprintln!("Cannot run the game. {}", error);
+        process::exit(1);
+    }
+}
 
-    let _sdl_mixer_context =
-        sdl2::mixer::init(INIT_MP3).expect("SDL Mixer initialization failed");
+fn run(options: &Options) -> Result<()> {
+    // Reading game data
+    let db_connection = open_database_at(&options.db_path)?;
+    let settings = Settings::load(&db_connection)?;
+    // Settings and texts always come from the database
+    let assets: Box<AssetSource> = match options.data_dir {
+        Some(ref dir) => Box::new(DirectoryAssets::new(dir.clone())),
+        None => Box::new(SqliteAssets::open(&options.db_path)?),
+    };
 
-    sdl2::mixer::allocate_channels(2);
+    // Initializing SDL2 variables
+    let sdl_context = sdl2::init().map_err(|e| Error::sdl("core", e))?;
 
-    let effects = sound::load_sounds();
+    // Init textures
+    let mut canvas =
+        graphics::init(&sdl_context, &settings, options.window_mode)?;
+    let texture_creator = canvas.texture_creator();
+    let textures = init_textures(
+        &texture_creator,
+        &*assets,
+        &db_connection,
+        &settings,
+    )?;
+
+    // Init sounds, the game goes on silently without them
+    let mut audio =
+        sound::open_audio(&sdl_context, &*assets, &settings, options.audio);
+    let preferences_path = preferences::preferences_path();
+    let mut preferences = match Preferences::load(&preferences_path) {
+        Ok(preferences) => preferences,
+        Err(error) => {
+            eprintln!("Cannot read the preferences. {}", error);
+            Preferences::default()
+        }
+    };
+    audio.apply_volumes(&preferences);
 
     // Init events
     let mut sdl_eventpump = sdl_context
         .event_pump()
-        .expect("SDL Event Pump initialization error.");
-    let sdl_event = sdl_context
-        .event()
-        .expect("SDL Event subsystem initialization error.");
-    sevend::objects::init_custom_events(&sdl_event);
+        .map_err(|e| Error::sdl("events", e))?;
 
     // Updating window configuration
-    configure_window(canvas.window_mut(), &textures);
-
-    // Init game variables
-    let mut map = Map::init()
-        .expect("Cannot run the game because of map generation error");
-    let start = map.get_location("start").unwrap();
-    let mut player = Player::init(start.0, start.1);
-    let mut monster = Kobold::init(&map);
-    let mut resources = Resources::init(&map, &player);
-    map.update(&player);
-    let mut happy_end = false;
-    let mut end = false;
+    configure_window(&mut canvas, &textures, options.window_mode)?;
+
+    // Init game variables, the unfinished game is resumed if there is one
+    let replay = match options.replay_path {
+        Some(ref path) => Some(Replay::read(path)?),
+        None => None,
+    };
+    let new_game_seed = match replay {
+        Some(ref replay) => Some(replay.seed),
+        None => options.seed,
+    };
+    let resumed_game = match (new_game_seed, &options.save_path) {
+        (Some(_), _) => None,
+        (None, _) if options.record_path.is_some() => None,
+        (None, &Some(ref save_path)) if save_path.is_file() => {
+            match save::read(save_path, &db_connection, &settings) {
+                Ok(game) => Some(game),
+                Err(error) => {
+                    eprintln!("Cannot resume the game. {}", error);
+                    None
+                }
+            }
+        }
+        (None, _) => None,
+    };
+    let mut game = match (resumed_game, new_game_seed) {
+        (Some(game), _) => game,
+        (None, Some(seed)) => GameState::init_with_seed(
+            seed,
+            &*assets,
+            &db_connection,
+            &settings,
+        )?,
+        (None, None) => GameState::init(&*assets, &db_connection, &settings)?,
+    };
+
+    graphics::check_map_area(&game.map, &textures, &settings)?;
+
+    // Either the game is recorded or a recorded one is played back
+    let mut recording: Option<Replay> = match options.record_path {
+        Some(_) => Some(Replay::new(game.seed())),
+        None => None,
+    };
+    let mut playback: Option<Playback> = match replay {
+        Some(replay) => Some(Playback::new(replay, REPLAY_STEP)),
+        None => None,
+    };
 
     // Init GUI parts
-    let mut textline = TextLine::init();
     let background_image = Background::init();
-    let mut resource_counter = ResourceCounter::init(&player);
-    let mut textscene = TextScene::init();
+    let mut resource_counter = ResourceCounter::init(&game.player);
 
     // Init GUI elements
-    let background = GUIElement::init("bg");
-    let gamearea = GUIElement::init("map");
-    let text = GUIElement::init("text");
-    let resource_place = GUIElement::init("flask");
-    let scene = GUIElement::init("scene");
-    let help = GUIElement::init("help");
-
-    // Play background music
-    let db_path: PathBuf = [".", DB_FILENAME].iter().collect();
-    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-    let db_connection =
-        Connection::open_with_flags(&db_path, flags).expect("Cannot read data");
-    let mut music_blob = db_connection
-        .blob_open(
-            DatabaseName::Main,
-            "musics",
-            "music",
-            1,
-            true, // Read-Only
-        )
-        .expect("Cannot read image blob.");
-    let mut music_bytes: Vec<u8> = Vec::new();
-    music_blob
-        .read_to_end(&mut music_bytes)
-        .expect("Cannot read image bytes.");
-    let music_stream = RWops::from_bytes(&music_bytes)
-        .expect("Cannot open image bytes as a stream.");
-    let music = music_stream.load_music().expect("Cannot load music");
-    music.play(-1).expect("Cannot play music");
+    let background = GUIElement::init("bg", &settings);
+    let gamearea = GUIElement::init("map", &settings);
+    let text = GUIElement::init("text", &settings);
+    let resource_place = GUIElement::init("flask", &settings);
+    let scene = GUIElement::init("scene", &settings);
+    let help = GUIElement::init("help", &settings);
 
     'running: loop {
+        let mut commands: Vec<Command> = Vec::new();
+
         // Events handling
         for event in sdl_eventpump.poll_iter() {
-            match event {
-                Event::Quit { .. } => {
-                    break 'running;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Return),
-                    ..
-                } => {
-                    if textscene.active {
-                        textscene.active = false;
-
-                        if textscene.scene.starts_with("end_") {
-                            break 'running;
+            if let Event::Quit { .. } = event {
+                if playback.is_none() {
+                    if let Some(ref save_path) = options.save_path {
+                        if let Err(error) = save::write(&game, save_path) {
+                            eprintln!("Cannot save the game. {}", error);
                         }
+                    }
+                    write_recording(&recording, &options.record_path)?;
+                }
+                break 'running;
+            }
 
-                        if end {
-                            textscene.active = true;
-                            textscene.scene = if happy_end {
-                                String::from("end_good")
-                            } else {
-                                String::from("end_bad")
-                            }
-                        }
+            // Volumes can be changed while a replay is watched too
+            if let Event::KeyDown {
+                keycode: Some(kcode),
+                ..
+            } = event
+            {
+                if audio_control(&kcode, &mut preferences) {
+                    audio.apply_volumes(&preferences);
+                    if let Err(error) = preferences.save(&preferences_path) {
+                        eprintln!("Cannot save the preferences. {}", error);
                     }
+                    continue;
+                }
+            }
+
+            // Player input is not used while a replay is watched
+            if let Some(ref mut playback) = playback {
+                match event {
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Space),
+                        ..
+                    } => playback.toggle_pause(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Right),
+                        ..
+                    } => playback.step(),
+                    _ => (),
                 }
+                continue;
+            }
+
+            let command: Option<Command> = match event {
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    ..
+                } => Some(Command::CloseScene),
                 Event::MouseButtonDown {
                     mouse_btn: MouseButton::Left,
                     clicks: 1,
-                    timestamp: e_timestamp,
-                    window_id: e_window_id,
                     x: e_x,
                     y: e_y,
                     ..
                 } => {
                     if resource_place.contains(e_x, e_y) {
-                        let false_event = Event::KeyDown {
-                            timestamp: e_timestamp + 1,
-                            window_id: e_window_id,
-                            keycode: Some(Keycode::R),
-                            scancode: Some(Scancode::R),
-                            keymod: NOMOD,
-                            repeat: false,
-                        };
-                        sdl_event.push_event(false_event).unwrap();
-                    }
-
-                    if gamearea.contains(e_x, e_y) {
+                        Some(Command::Refill)
+                    } else if gamearea.contains(e_x, e_y) {
                         let (gamearea_x, gamearea_y): (i32, i32) = gamearea
                                 .into_relative(e_x, e_y);
                         let texture_side: f32 =
@@ -181,146 +348,113 @@ fn main() {
                         let map_y: usize =
                             (gamearea_y as f32 / texture_side).floor() as usize;
 
-                        map.toggle_mark(map_x, map_y);
-                    }
-
-                    if help.contains(e_x, e_y) {
-                        let false_event = Event::KeyDown {
-                            timestamp: e_timestamp + 1,
-                            window_id: e_window_id,
-                            keycode: Some(Keycode::H),
-                            scancode: Some(Scancode::H),
-                            keymod: NOMOD,
-                            repeat: false,
-                        };
-                        sdl_event.push_event(false_event).unwrap();
+                        Some(Command::ToggleMark(map_x, map_y))
+                    } else if help.contains(e_x, e_y) {
+                        Some(Command::ShowHelp)
+                    } else {
+                        None
                     }
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::H),
                     ..
-                } => {
-                    textscene.active = true;
-                    textscene.scene = String::from("controls");
-                }
+                } => Some(Command::ShowHelp),
                 Event::KeyDown {
                     keycode: Some(kcode),
                     ..
-                } => {
-                    if !textscene.active {
-                        // Update game
-                        if player.update(
-                            &kcode,
-                            &map,
-                            &monster,
-                            &resources,
-                            &sdl_event,
-                        ) {
-                            map.update(&player);
-                            resource_counter.update(&player);
-                            textline.update();
-                            monster.update(&map);
-                        }
-                    }
-                }
-                ref custom_event if custom_event.is_user_event() => {
-                    //{{{ EventResourceRefill
-                    if let Some(resource_refill) =
-                        custom_event.as_user_event_type::<EventResourceRefill>()
-                    {
-                        if resource_refill.success {
-                            textline.set_situation("resource_refill");
-                            sound::play_effect(&effects["match.wav"]);
-                        } else {
-                            textline.set_situation("resource_absent");
-                        }
-                    }
-                    //}}}
+                } => command_from_key(&kcode),
+                _ => None,
+            };
+
+            if let Some(command) = command {
+                commands.push(command);
+            }
+        }
+
+        if let Some(ref mut playback) = playback {
+            if let Some(command) = playback.next_command(Instant::now()) {
+                commands.push(command);
+            }
+        }
 
-                    //{{{ EventResourceFound
-                    if let Some(resource_found) =
-                        custom_event.as_user_event_type::<EventResourceFound>()
-                    {
-                        resources.process_event(&resource_found);
-                        player.add_view_resource_count();
+        for command in commands {
+            if let Some(ref mut recording) = recording {
+                recording.record(&command);
+            }
 
-                        textline.set_any_situation("resource_found");
+            for game_event in game.apply(command) {
+                match game_event {
+                    GameEvent::ResourceRefill { success: true } => {
+                        audio.play_event_sound("resource_refill")
                     }
-                    //}}}
-
-                    //{{{ EventResourceGone
-                    if let Some(_resource_gone) =
-                        custom_event.as_user_event_type::<EventResourceGone>()
-                    {
-                        textline.set_any_situation("resource_gone");
-                        sound::play_effect(&effects["fizzing.wav"]);
+                    GameEvent::ResourceGone => {
+                        audio.play_event_sound("resource_gone")
                     }
-                    //}}}
-
-                    //{{{ EventObstacleFound
-                    if let Some(obstacle_found) =
-                        custom_event.as_user_event_type::<EventObstacleFound>()
-                    {
-                        textline.set_situation(&obstacle_found.text);
+                    GameEvent::MonsterStep { location } => {
+                        play_event_sound_at(
+                            &*audio,
+                            "monster_step",
+                            &game,
+                            location,
+                        )
                     }
-                    //}}}
-
-                    //{{{ EventCurioFound
-                    if let Some(curio_found) =
-                        custom_event.as_user_event_type::<EventCurioFound>()
-                    {
-                        textscene.active = true;
-                        textscene.scene = curio_found.scene;
-                        match textscene.scene.as_str() {
-                            "item" => {
-                                monster.die();
-                                happy_end = true;
-                                sound::play_effect(&effects["shout.wav"]);
-                            }
-                            "lair" => if !happy_end {
-                                end = true;
-                            },
-                            "children" => {
-                                happy_end = true;
-                                end = true;
-                            }
-                            _ => (),
-                        }
+                    GameEvent::MonsterBreath { location } => {
+                        play_event_sound_at(
+                            &*audio,
+                            "monster_breath",
+                            &game,
+                            location,
+                        )
                     }
-                    //}}}
-
-                    //{{{ EventPlayerInDanger
-                    if let Some(_in_danger) =
-                        custom_event.as_user_event_type::<EventPlayerInDanger>()
-                    {
-                        textline.set_any_situation("danger");
+                    GameEvent::MonsterDied => {
+                        // The roar is heard wherever the item is taken
+                        let position = sound::loud_sound_position(
+                            &game.map,
+                            (game.player.x, game.player.y),
+                            (game.monster.x, game.monster.y),
+                        );
+                        audio.play_event_sound_at(
+                            "monster_died",
+                            Some(position),
+                        )
                     }
-                    //}}}
-
-                    //{{{ EventPlayerMeetMonster
-                    if let Some(_meet_monster) = custom_event
-                        .as_user_event_type::<EventPlayerMeetMonster>()
-                    {
-                        textscene.active = true;
-                        textscene.scene = String::from("monster");
-                        end = true;
+                    GameEvent::GameOver => {
+                        if playback.is_none() {
+                            if let Some(ref save_path) = options.save_path {
+                                if let Err(error) = save::remove(save_path) {
+                                    eprintln!(
+                                        "Cannot remove the save. {}",
+                                        error
+                                    );
+                                }
+                            }
+                            write_recording(&recording, &options.record_path)?;
+                        }
+                        break 'running;
                     }
-                    //}}}
+                    _ => (),
                 }
-                _ => (),
             }
+            resource_counter.update(&game.player);
         }
 
+        // Play music of the game state
+        audio.set_music_state(game.music_state());
+
         // Start drawing
         canvas.clear();
 
         background.draw(&textures, &mut canvas, vec![&background_image]);
-        gamearea.draw(&textures, &mut canvas, vec![&map, &player]);
+        gamearea.draw(
+            &textures,
+            &mut canvas,
+            vec![&game.map, &game.monster, &game.player],
+        );
         resource_place.draw(&textures, &mut canvas, vec![&resource_counter]);
-        if textscene.active {
-            scene.draw(&textures, &mut canvas, vec![&textscene]);
+        if game.textscene.active {
+            scene.draw(&textures, &mut canvas, vec![&game.textscene]);
         } else {
-            text.draw(&textures, &mut canvas, vec![&textline]);
+            text.draw(&textures, &mut canvas, vec![&game.textline]);
         }
 
         // Stop drawing
@@ -328,4 +462,151 @@ fn main() {
 
         ::std::thread::sleep(Duration::from_millis(16));
     }
+
+    Ok(())
+}
+
+/*
+ * The sound is heard from the map location, relative to the player
+ */
+fn play_event_sound_at(
+    audio: &AudioBackend,
+    event: &str,
+    game: &GameState,
+    location: (usize, usize),
+) {
+    let listener = (game.player.x, game.player.y);
+    let position = sound::sound_position(&game.map, listener, location);
+    if let Some(position) = position {
+        audio.play_event_sound_at(event, Some(position));
+    }
+}
+
+fn write_recording(
+    recording: &Option<Replay>,
+    record_path: &Option<PathBuf>,
+) -> Result<()> {
+    match (recording, record_path) {
+        (&Some(ref recording), &Some(ref path)) => recording.write(path),
+        _ => Ok(()),
+    }
+}
+
+/*
+ * Keyboard layout of the game
+ */
+fn command_from_key(key: &Keycode) -> Option<Command> {
+    match *key {
+        // Movement
+        Keycode::Up | Keycode::Kp8 | Keycode::Num8 | Keycode::W => {
+            Some(Command::Move(0, -1))
+        }
+        Keycode::Down | Keycode::Kp2 | Keycode::Num2 | Keycode::S => {
+            Some(Command::Move(0, 1))
+        }
+        Keycode::Left | Keycode::Kp4 | Keycode::Num4 | Keycode::A => {
+            Some(Command::Move(-1, 0))
+        }
+        Keycode::Right | Keycode::Kp6 | Keycode::Num6 | Keycode::D => {
+            Some(Command::Move(1, 0))
+        }
+        Keycode::Kp1 | Keycode::Num1 | Keycode::Z => Some(Command::Move(-1, 1)),
+        Keycode::Kp3 | Keycode::Num3 | Keycode::C => Some(Command::Move(1, 1)),
+        Keycode::Kp7 | Keycode::Num7 | Keycode::Q => {
+            Some(Command::Move(-1, -1))
+        }
+        Keycode::Kp9 | Keycode::Num9 | Keycode::E => Some(Command::Move(1, -1)),
+
+        // Actions
+        Keycode::R | Keycode::Kp0 | Keycode::Num0 => Some(Command::Refill),
+
+        _ => None,
+    }
+}
+
+/*
+ * False for keys which do not change the audio
+ */
+fn audio_control(key: &Keycode, preferences: &mut Preferences) -> bool {
+    match *key {
+        Keycode::M => preferences.toggle_mute(),
+        Keycode::Minus | Keycode::KpMinus => {
+            preferences.change_volume(Volume::Master, false)
+        }
+        Keycode::Equals | Keycode::KpPlus => {
+            preferences.change_volume(Volume::Master, true)
+        }
+        Keycode::LeftBracket => preferences.change_volume(Volume::Music, false),
+        Keycode::RightBracket => preferences.change_volume(Volume::Music, true),
+        Keycode::Semicolon => preferences.change_volume(Volume::Effects, false),
+        Keycode::Quote => preferences.change_volume(Volume::Effects, true),
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> ::std::result::Result<Options, String> {
+        parse_options(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    #[test]
+    fn no_options() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.seed, None);
+        assert_eq!(options.db_path, database_path(None));
+        assert_eq!(options.save_path, Some(save::save_path()));
+        assert_eq!(options.window_mode, WindowMode::default());
+        assert!(options.audio);
+    }
+
+    #[test]
+    fn all_options() {
+        let options = parse(&[
+            "--seed",
+            "42",
+            "--lang",
+            "ru",
+            "--fullscreen",
+            "--scale",
+            "1.5",
+            "--no-audio",
+        ]).unwrap();
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.save_path, None);
+        assert_eq!(options.db_path, database_path(Some("ru")));
+        assert!(options.window_mode.fullscreen);
+        assert_eq!(options.window_mode.scale, 1.5);
+        assert!(!options.audio);
+
+        let options = parse(&["--lang", "ru", "--db", "test.sqlite3"]);
+        assert_eq!(options.unwrap().db_path, PathBuf::from("test.sqlite3"));
+
+        let options = parse(&["--data", "data"]).unwrap();
+        assert_eq!(options.data_dir, Some(PathBuf::from("data")));
+
+        let options = parse(&["--record", "bug.txt"]).unwrap();
+        assert_eq!(options.record_path, Some(PathBuf::from("bug.txt")));
+        assert_eq!(options.replay_path, None);
+        assert_eq!(options.save_path, None);
+
+        let options = parse(&["--save", "test_save.sqlite3", "--seed", "7"]);
+        assert_eq!(
+            options.unwrap().save_path,
+            Some(PathBuf::from("test_save.sqlite3"))
+        );
+    }
+
+    #[test]
+    fn bad_options() {
+        assert!(parse(&["--seed"]).is_err());
+        assert!(parse(&["--seed", "many"]).is_err());
+        assert!(parse(&["--scale", "0"]).is_err());
+        assert!(parse(&["--windowed"]).is_err());
+        assert!(parse(&["--replay", "bug.txt", "--seed", "1"]).is_err());
+        assert!(parse(&["--replay", "bug.txt", "--record", "a.txt"]).is_err());
+    }
 }
@@ -1,23 +1,23 @@
-use std::path::PathBuf;
 use std::collections::HashMap;
 
-use rand::{Rng, SeedableRng, StdRng};
-use rusqlite::{Connection, DatabaseName, OpenFlags};
-use std::io::Read;
+use rand::Rng;
+use rusqlite::Connection;
 use pathfinding::astar::astar;
 
-use super::get_setting;
-use super::generate_seed;
+use super::get_situations;
+use super::GameRng;
+use super::{Error, Result};
+use super::fov::field_of_view;
 use super::objects::Player;
+use super::settings::Settings;
+use super::assets::{Asset, AssetKind, AssetSource};
+use super::save::{get_value, set_value};
 
 //const CARDS_FIELDS_COUNT: usize = 18;
 const ENDS_COUNT: usize = 6;
 
-const CARDS_MAP_SIDE: usize = 3;
-
-use super::DB_FILENAME;
-const DB_TABLE_W_CARDS: &'static str = "cards";
-const DB_TABLE_W_CARDS_COLUMN: &'static str = "tiles";
+// Placements tried before a field of cards is given up
+const FIELD_MAX_STEPS: usize = 20000;
 
 //{{{ Tile
 #[derive(Copy, Clone)]
@@ -27,6 +27,26 @@ pub enum TileType {
     Obstacle,
     Curiosity,
 }
+impl TileType {
+    fn name(&self) -> &'static str {
+        match *self {
+            TileType::Floor => "floor",
+            TileType::Wall => "wall",
+            TileType::Obstacle => "obstacle",
+            TileType::Curiosity => "curiosity",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<TileType> {
+        match name {
+            "floor" => Some(TileType::Floor),
+            "wall" => Some(TileType::Wall),
+            "obstacle" => Some(TileType::Obstacle),
+            "curiosity" => Some(TileType::Curiosity),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub enum EndType {
@@ -36,12 +56,54 @@ pub enum EndType {
     Item,
     Rest,
 }
+impl EndType {
+    const ALL: [EndType; 5] = [
+        EndType::Children,
+        EndType::Body,
+        EndType::Lair,
+        EndType::Item,
+        EndType::Rest,
+    ];
+
+    // Name of the scene shown when the curio is found
+    pub fn scene(&self) -> &'static str {
+        match *self {
+            EndType::Children => "children",
+            EndType::Body => "body",
+            EndType::Lair => "lair",
+            EndType::Item => "item",
+            EndType::Rest => "rest",
+        }
+    }
+
+    pub fn from_scene(scene: &str) -> Option<EndType> {
+        EndType::ALL.iter().cloned().find(|end| end.scene() == scene)
+    }
+
+    // Letter of the curio in the text pictures of the map
+    pub fn glyph(&self) -> char {
+        match *self {
+            EndType::Children => 'C',
+            EndType::Body => 'B',
+            EndType::Lair => 'L',
+            EndType::Item => 'I',
+            EndType::Rest => 'R',
+        }
+    }
+
+    pub fn from_glyph(glyph: char) -> Option<EndType> {
+        EndType::ALL.iter().cloned().find(|end| end.glyph() == glyph)
+    }
+}
 
 #[derive(Clone)]
 pub struct Tile {
     pub ttype: TileType,
     pub passable: bool,
-    pub visible: bool,
+    // Seen at least once, kept for good
+    pub explored: bool,
+    // In the light of the lamp right now, not saved
+    pub lit: bool,
     curiosity_checked: bool,
     pub search_text: String,
 
@@ -49,11 +111,7 @@ pub struct Tile {
     pub icon: String,
 }
 impl Tile {
-    pub fn init_regular<T: Rng>(
-        tile_type: TileType,
-        db_conn: &Connection,
-        rng: &mut T,
-    ) -> Option<Tile> {
+    pub fn init_regular(tile_type: TileType) -> Option<Tile> {
         if let TileType::Curiosity = tile_type {
             return None;
         }
@@ -67,52 +125,36 @@ impl Tile {
             _ => true,
         };
 
-        let message: String = match tile_type {
-            TileType::Obstacle => {
-                let query = String::from("select situation ") + "from messages "
-                    + "where situation like ?;";
-                let mut statement = db_conn.prepare(&query).unwrap();
-
-                let obstacles: Vec<String> = statement
-                    .query_map(&[&"obstacle%"], |row| {
-                        let s: String = row.get(0);
-                        s
-                    })
-                    .unwrap()
-                    .map(|row| row.unwrap())
-                    .collect();
-
-                match rng.choose(&obstacles) {
-                    Some(obstacle) => obstacle.clone(),
-                    None => String::from("empty"),
-                }
-            }
-            _ => String::from("empty"),
-        };
-
         Some(Tile {
             ttype: tile_type,
             passable: tile_pass,
-            visible: false,
+            explored: false,
+            lit: false,
             curiosity_checked: false,
-            search_text: message,
+            search_text: String::from("empty"),
             icon: tile_image,
         })
     }
 
+    /*
+     * `messages` are the obstacle situations, one of them is picked
+     */
+    pub fn init_obstacle<T: Rng>(messages: &[String], rng: &mut T) -> Tile {
+        let mut tile = Tile::init_regular(TileType::Obstacle).unwrap();
+        if let Some(obstacle) = rng.choose(messages) {
+            tile.search_text = obstacle.clone();
+        }
+        tile
+    }
+
     pub fn init_curio(end_type: EndType) -> Option<Tile> {
-        let scene: String = String::from(match end_type {
-            EndType::Children => "children",
-            EndType::Body => "body",
-            EndType::Lair => "lair",
-            EndType::Item => "item",
-            EndType::Rest => "rest",
-        });
+        let scene: String = String::from(end_type.scene());
 
         Some(Tile {
             ttype: TileType::Curiosity,
             passable: true,
-            visible: false,
+            explored: false,
+            lit: false,
             curiosity_checked: false,
             search_text: scene,
             icon: String::from("floor.png"),
@@ -121,83 +163,234 @@ impl Tile {
 }
 //}}}
 
+//{{{ Slot
+/*
+ * Hand-placed spots of the cards. Map generation uses them
+ * before placing anything at random.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Slot {
+    // 'O', an obstacle always placed there
+    Obstacle,
+    // 'o', an oil spot always placed there
+    Oil,
+    // '*', curios are placed there before dead ends
+    Curio,
+    // 'K', the kobold starts from one of these instead of its lair
+    KoboldSpawn,
+    // '?', a wall or a floor, decided for every map
+    MaybeWall,
+}
+//}}}
+
 //{{{ Card
+#[derive(Clone, Copy)]
+enum Side {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
 #[derive(Clone)]
-struct Card {
+pub struct Card {
+    name: String,
     // Row of columns!!!
     // tiles[x][y]
     tiles: Vec<Vec<Tile>>,
+    // Same layout as tiles
+    slots: Vec<Vec<Option<Slot>>>,
+    // Fixed cards are never rotated or flipped
+    fixed: bool,
 }
 impl Card {
-    pub fn new(id: i64, db_conn: &Connection) -> Result<Card, ()> {
-        if let Ok(mut tiles_blob) = db_conn.blob_open(
-            DatabaseName::Main,
-            DB_TABLE_W_CARDS,
-            DB_TABLE_W_CARDS_COLUMN,
-            id,
-            true, // Read-Only
-        ) {
-            let mut tiles_string =
-                String::with_capacity(tiles_blob.size() as usize);
-            if let Ok(_) = tiles_blob.read_to_string(&mut tiles_string) {
-                let card_side: usize = tiles_string.lines().count();
-
-                let mut tiles_chars: Vec<Vec<char>> =
-                    // Row of columns
-                    Vec::with_capacity(card_side)
-                ;
-                for _x in 0..card_side {
-                    // Columns
-                    let column: Vec<char> = vec!['z'; card_side];
-                    tiles_chars.push(column);
-                }
-                for (y, line) in tiles_string.lines().enumerate() {
-                    for (x, c) in line.chars().enumerate() {
-                        tiles_chars[x][y] = c;
-                    }
-                }
+    pub fn new(name: &str, tiles_bytes: &[u8]) -> Result<Card> {
+        let tiles_string = String::from_utf8(tiles_bytes.to_vec()).map_err(
+            |_| Error::corrupt_blob(AssetKind::Card.table(), name),
+        )?;
 
-                let mut random_number_generator = StdRng::new()
-                    .expect("Failed to read randomness from operating system.");
+        Card::from_text(name, &tiles_string)
+    }
 
-                let mut card = Card {
-                    // Row of columns
-                    tiles: Vec::with_capacity(card_side),
-                };
-                for x in 0..card_side {
-                    // Columns themselves
-                    card.tiles.push(Vec::with_capacity(card_side));
-
-                    for y in 0..card_side {
-                        let tile_char: char = tiles_chars[x][y];
-                        let tile: Tile = match tile_char {
-                            '#' => Tile::init_regular(
-                                TileType::Wall,
-                                db_conn,
-                                &mut random_number_generator,
-                            ).unwrap(),
-                            '_' => Tile::init_regular(
-                                TileType::Floor,
-                                db_conn,
-                                &mut random_number_generator,
-                            ).unwrap(),
-                            _ => Tile::init_regular(
-                                TileType::Wall,
-                                db_conn,
-                                &mut random_number_generator,
-                            ).unwrap(),
-                        };
-                        card.tiles[x].push(tile);
+    pub fn from_text(name: &str, tiles_string: &str) -> Result<Card> {
+        let card_side: usize = tiles_string.lines().count();
+        if card_side == 0 {
+            return Err(Error::BadCardLayout {
+                card: String::from(name),
+                reason: String::from("card is empty"),
+            });
+        }
+
+        let mut tiles_chars: Vec<Vec<char>> =
+            // Row of columns
+            Vec::with_capacity(card_side)
+        ;
+        for _x in 0..card_side {
+            // Columns
+            let column: Vec<char> = vec!['#'; card_side];
+            tiles_chars.push(column);
+        }
+        for (y, line) in tiles_string.lines().enumerate() {
+            // Cards are square, so they can be turned
+            let line_length = line.chars().count();
+            if line_length != card_side {
+                return Err(Error::BadCardLayout {
+                    card: String::from(name),
+                    reason: format!(
+                        "line {} has {} tiles, the card side is {}",
+                        y + 1,
+                        line_length,
+                        card_side
+                    ),
+                });
+            }
+            for (x, c) in line.chars().enumerate() {
+                tiles_chars[x][y] = c;
+            }
+        }
+
+        let mut card = Card {
+            name: String::from(name),
+            // Row of columns
+            tiles: Vec::with_capacity(card_side),
+            slots: vec![vec![None; card_side]; card_side],
+            fixed: false,
+        };
+        for x in 0..card_side {
+            // Columns themselves
+            card.tiles.push(Vec::with_capacity(card_side));
+
+            for y in 0..card_side {
+                let tile_char: char = tiles_chars[x][y];
+                let (ttype, slot) = match tile_char {
+                    '#' => (TileType::Wall, None),
+                    '_' => (TileType::Floor, None),
+                    'O' => (TileType::Obstacle, Some(Slot::Obstacle)),
+                    'o' => (TileType::Floor, Some(Slot::Oil)),
+                    '*' => (TileType::Floor, Some(Slot::Curio)),
+                    'K' => (TileType::Floor, Some(Slot::KoboldSpawn)),
+                    // A wall until the map is made, for lining cards up
+                    '?' => (TileType::Wall, Some(Slot::MaybeWall)),
+                    _ => {
+                        return Err(Error::BadCardLayout {
+                            card: String::from(name),
+                            reason: format!(
+                                "line {} has unknown glyph '{}'",
+                                y + 1,
+                                tile_char
+                            ),
+                        })
                     }
-                }
-                Ok(card)
-            } else {
-                Err(())
+                };
+                card.tiles[x].push(Tile::init_regular(ttype).unwrap());
+                card.slots[x][y] = slot;
             }
-        } else {
-            Err(())
+        }
+
+        // Otherwise nothing on it could be reached
+        if card.border().iter().all(|edge| !edge.contains('_')) {
+            return Err(Error::BadCardLayout {
+                card: String::from(name),
+                reason: String::from("no openings on the sides"),
+            });
+        }
+        Ok(card)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn side(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.fixed
+    }
+
+    /*
+     * Top, right, bottom and left sides of the card as '#' and '_',
+     * from left to right or from top to bottom
+     */
+    pub fn border(&self) -> [String; 4] {
+        let text = |side: Side| {
+            self.edge(side)
+                .iter()
+                .map(|&passable| if passable { '_' } else { '#' })
+                .collect::<String>()
+        };
+        [
+            text(Side::Top),
+            text(Side::Right),
+            text(Side::Bottom),
+            text(Side::Left),
+        ]
+    }
+
+    pub fn slot_count(&self, slot: Slot) -> usize {
+        self.slots
+            .iter()
+            .flat_map(|column| column.iter())
+            .filter(|&&other| other == Some(slot))
+            .count()
+    }
+
+    /*
+     * One of the 8 symmetries of the square: transforms 0-3 rotate
+     * the card clockwise by that many quarter turns, 4-7 do the same
+     * after flipping it left to right. Transform 0 keeps it as is.
+     */
+    pub fn transformed(&self, transform: usize) -> Card {
+        Card {
+            name: self.name.clone(),
+            tiles: transform_square(&self.tiles, transform),
+            slots: transform_square(&self.slots, transform),
+            fixed: self.fixed,
         }
     }
+
+    /*
+     * Passability of the border tiles on one side,
+     * from left to right or from top to bottom
+     */
+    fn edge(&self, side: Side) -> Vec<bool> {
+        let last = self.tiles.len() - 1;
+        let border: Vec<&Tile> = match side {
+            Side::Top => self.tiles.iter().map(|column| &column[0]).collect(),
+            Side::Bottom => {
+                self.tiles.iter().map(|column| &column[last]).collect()
+            }
+            Side::Left => self.tiles[0].iter().collect(),
+            Side::Right => self.tiles[last].iter().collect(),
+        };
+
+        border.iter().map(|tile| tile.passable).collect()
+    }
+}
+
+fn transform_square<T: Clone>(
+    square: &Vec<Vec<T>>,
+    transform: usize,
+) -> Vec<Vec<T>> {
+    let side = square.len();
+    let mut square = square.clone();
+
+    if transform >= 4 {
+        square.reverse();
+    }
+    for _turn in 0..transform % 4 {
+        // Clockwise: (x, y) goes to (side - 1 - y, x)
+        let mut turned = square.clone();
+        for (x, column) in square.iter().enumerate() {
+            for (y, cell) in column.iter().enumerate() {
+                turned[side - 1 - y][x] = cell.clone();
+            }
+        }
+        square = turned;
+    }
+
+    square
 }
 //}}}
 
@@ -207,82 +400,258 @@ pub struct Map {
     // Row of columns!!!
     // tiles[x][y]
     pub tiles: Vec<Vec<Tile>>,
+    // Same as tiles.len() and tiles[x].len()
+    pub width: usize,
+    pub height: usize,
     pub marks: Vec<(usize, usize)>,
     special_locations: HashMap<String, (usize, usize)>,
+    // Only known right after generation, they are not saved
+    slots: Vec<((usize, usize), Slot)>,
 }
 impl Map {
     //{{{ public
-    pub fn init() -> Result<Map, u8> {
-        let fields = init_cards().expect("Failed to init cards.");
+    pub fn init_with_seed(
+        seed: usize,
+        assets: &AssetSource,
+        db_conn: &Connection,
+        settings: &Settings,
+    ) -> Result<Map> {
+        Map::generate(assets, db_conn, settings, &mut GameRng::new(seed))
+    }
 
-        let tries_max: u8 = match get_setting("map_max_tries") {
-            Some(value) => value,
-            None => 100,
-        };
+    /*
+     * Every random decision of map generation is taken from the `rng`,
+     * so it can be used further by the rest of the game.
+     */
+    pub fn generate(
+        assets: &AssetSource,
+        db_conn: &Connection,
+        settings: &Settings,
+        rng: &mut GameRng,
+    ) -> Result<Map> {
+        Map::generate_with_reasons(assets, db_conn, settings, rng)
+            .map(|(map, _reasons)| map)
+    }
 
-        let seed: usize = generate_seed();
-        println!("Map generation seed: {}", seed);
-        let seed: &[_] = &[seed];
-        let mut rng: StdRng = SeedableRng::from_seed(seed);
-        let mut map: Result<Map, u8> = Err(tries_max);
-        let mut try_n: u8 = 0;
-        while try_n < tries_max {
-            let cards_field = generate_field(&fields, &mut rng);
-            let tiles = generate_map(&cards_field);
+    /*
+     * Same as `generate`, but the reasons of the failed tries
+     * are returned along with the map
+     */
+    pub fn generate_with_reasons(
+        assets: &AssetSource,
+        db_conn: &Connection,
+        settings: &Settings,
+        rng: &mut GameRng,
+    ) -> Result<(Map, Vec<String>)> {
+        let field_width = settings.field_width as usize;
+        let field_height = settings.field_height as usize;
+        let fields = init_cards(assets, field_width * field_height)?;
+        let obstacles = get_situations(db_conn, "obstacle")?;
+
+        let tries_max: u8 = settings.map_max_tries;
+        let mut reasons: Vec<String> = Vec::with_capacity(tries_max as usize);
+        for _try_n in 0..tries_max {
+            let cards_field = match generate_field(
+                &fields,
+                field_width,
+                field_height,
+                rng,
+            ) {
+                Some(cards_field) => cards_field,
+                None => {
+                    reasons.push(String::from("Cards do not fit together"));
+                    continue;
+                }
+            };
+            let (tiles, slots) = generate_map(&cards_field);
             let mut new_map = Map {
+                width: tiles.len(),
+                height: tiles[0].len(),
                 tiles,
                 marks: Vec::new(),
                 special_locations: HashMap::with_capacity(ENDS_COUNT),
+                slots,
             };
-            new_map.add_obstacles();
+            new_map.resolve_maybe_walls(rng);
+            new_map.add_obstacles(&obstacles, settings.obstacle_max, rng);
+
+            match new_map.add_curio(rng) {
+                Ok(_) => return Ok((new_map, reasons)),
+                Err(reason) => reasons.push(reason),
+            }
+        }
+
+        Err(Error::MapGenerationExhausted(reasons))
+    }
 
-            if new_map.add_curio().is_ok() {
-                map = Ok(new_map.clone());
-                break;
+    //{{{ save
+    pub fn save(&self, save_conn: &Connection) -> Result<()> {
+        set_value(save_conn, "map_width", self.width as i64)?;
+        set_value(save_conn, "map_height", self.height as i64)?;
+
+        let mut statement = save_conn.prepare(
+            "insert into tiles(
+                x, y, type, passable, explored,
+                curiosity_checked, search_text, icon
+            ) values (?, ?, ?, ?, ?, ?, ?, ?);",
+        )?;
+        for (x, column) in self.tiles.iter().enumerate() {
+            for (y, tile) in column.iter().enumerate() {
+                statement.execute(&[
+                    &(x as i64),
+                    &(y as i64),
+                    &tile.ttype.name(),
+                    &tile.passable,
+                    &tile.explored,
+                    &tile.curiosity_checked,
+                    &tile.search_text,
+                    &tile.icon,
+                ])?;
             }
+        }
 
-            try_n += 1;
+        for &(x, y) in self.marks.iter() {
+            save_conn.execute(
+                "insert into marks(x, y) values (?, ?);",
+                &[&(x as i64), &(y as i64)],
+            )?;
         }
-        map
+
+        for (name, &(x, y)) in self.special_locations.iter() {
+            save_conn.execute(
+                "insert into special_locations(name, x, y) values (?, ?, ?);",
+                &[name, &(x as i64), &(y as i64)],
+            )?;
+        }
+
+        Ok(())
     }
+    //}}}
 
-    //{{{ update
-    pub fn update(&mut self, player: &Player) {
-        let start_x =
-            match player.x.checked_sub(player.get_view_distance() as usize) {
-                Some(x) => x,
-                None => 0,
-            };
-        let start_y =
-            match player.y.checked_sub(player.get_view_distance() as usize) {
-                Some(y) => y,
-                None => 0,
+    //{{{ load
+    pub fn load(save_conn: &Connection) -> Result<Map> {
+        let width: i64 = get_value(save_conn, "map_width")?;
+        let height: i64 = get_value(save_conn, "map_height")?;
+        let floor = Tile::init_regular(TileType::Floor).unwrap();
+        let mut tiles: Vec<Vec<Tile>> =
+            vec![vec![floor; height as usize]; width as usize];
+
+        let mut statement = save_conn.prepare(
+            "select
+                x, y, type, passable, explored,
+                curiosity_checked, search_text, icon
+            from tiles;",
+        )?;
+        let rows = statement.query_map(&[], |row| {
+            let x: i64 = row.get(0);
+            let y: i64 = row.get(1);
+            let ttype: String = row.get(2);
+            let tile = Tile {
+                ttype: TileType::Floor,
+                passable: row.get(3),
+                explored: row.get(4),
+                lit: false,
+                curiosity_checked: row.get(5),
+                search_text: row.get(6),
+                icon: row.get(7),
             };
+            (x as usize, y as usize, ttype, tile)
+        })?;
+        for maybe_row_content in rows {
+            let (x, y, ttype, mut tile) = maybe_row_content?;
+            let bad_tile =
+                || Error::corrupt_blob("tiles", format!("{} {}", x, y));
+            tile.ttype = TileType::from_name(&ttype).ok_or_else(&bad_tile)?;
+            *tiles
+                .get_mut(x)
+                .and_then(|column| column.get_mut(y))
+                .ok_or_else(&bad_tile)? = tile;
+        }
 
-        let mut end_x = player.x + player.get_view_distance() as usize;
-        let mut end_y = player.y + player.get_view_distance() as usize;
-        let map_side = self.tiles.len();
-        if end_x > map_side {
-            end_x = map_side
-        };
-        if end_y > map_side {
-            end_y = map_side
-        };
+        let mut statement =
+            save_conn.prepare("select x, y from marks order by x, y;")?;
+        let marks: Vec<(usize, usize)> = statement
+            .query_map(&[], |row| {
+                let x: i64 = row.get(0);
+                let y: i64 = row.get(1);
+                (x as usize, y as usize)
+            })?
+            .collect::<::rusqlite::Result<Vec<(usize, usize)>>>()?;
+
+        let mut statement =
+            save_conn.prepare("select name, x, y from special_locations;")?;
+        let mut special_locations = HashMap::with_capacity(ENDS_COUNT);
+        for maybe_row_content in statement.query_map(&[], |row| {
+            let name: String = row.get(0);
+            let x: i64 = row.get(1);
+            let y: i64 = row.get(2);
+            (name, (x as usize, y as usize))
+        })? {
+            let (name, location) = maybe_row_content?;
+            special_locations.insert(name, location);
+        }
 
-        for x in start_x..end_x {
-            for y in start_y..end_y {
-                self.tiles[x][y].visible = true;
+        Ok(Map {
+            tiles,
+            width: width as usize,
+            height: height as usize,
+            marks,
+            special_locations,
+            slots: Vec::new(),
+        })
+    }
+    //}}}
+
+    //{{{ update
+    /*
+     * Tiles in the light of the lamp are lit until the next update
+     * and explored for good. Walls and obstacles hide what is
+     * behind them.
+     */
+    pub fn update(&mut self, player: &Player) {
+        let lit = field_of_view(
+            (player.x, player.y),
+            player.get_view_distance() as usize,
+            self.width,
+            self.height,
+            |x, y| self.is_opaque(&(x, y)),
+        );
+
+        for (column, lit_column) in self.tiles.iter_mut().zip(lit.iter()) {
+            for (tile, &tile_lit) in column.iter_mut().zip(lit_column.iter()) {
+                tile.lit = tile_lit;
+                if tile_lit {
+                    tile.explored = true;
+                }
             }
         }
     }
+
+    /*
+     * Walls and obstacles stop light and muffle sounds
+     */
+    pub fn is_opaque(&self, location: &(usize, usize)) -> bool {
+        let &(x, y) = location;
+        match self.tiles[x][y].ttype {
+            TileType::Wall | TileType::Obstacle => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_lit(&self, location: &(usize, usize)) -> bool {
+        let &(x, y) = location;
+        self.tiles
+            .get(x)
+            .and_then(|column| column.get(y))
+            .map_or(false, |tile| tile.lit)
+    }
     //}}}
 
     //{{{ neighbours
     pub fn get_neighbours(
         &self,
         location: &(usize, usize),
-    ) -> Vec<((usize, usize), u8)> {
-        let max_coord = self.tiles.len();
+    ) -> Vec<((usize, usize), usize)> {
         let &(lx, ly) = location;
         let min_x = match lx.checked_sub(1) {
             Some(x) => x,
@@ -292,9 +661,10 @@ impl Map {
             Some(y) => y,
             None => 0,
         };
-        let max_x = (lx + 2).min(max_coord);
-        let max_y = (ly + 2).min(max_coord);
-        let mut neighbours: Vec<((usize, usize), u8)> = Vec::with_capacity(8);
+        let max_x = (lx + 2).min(self.width);
+        let max_y = (ly + 2).min(self.height);
+        let mut neighbours: Vec<((usize, usize), usize)> =
+            Vec::with_capacity(8);
 
         for x in min_x..max_x {
             for y in min_y..max_y {
@@ -314,15 +684,32 @@ impl Map {
     }
     //}}}
 
+    //{{{ slots
+    /*
+     * Hand-placed spots of one kind, empty for loaded maps
+     */
+    pub fn slots(&self, slot: Slot) -> Vec<(usize, usize)> {
+        self.slots
+            .iter()
+            .filter(|&&(_, other)| other == slot)
+            .map(|&(location, _)| location)
+            .collect()
+    }
+
+    fn has_slot(&self, location: &(usize, usize)) -> bool {
+        self.slots.iter().any(|&(other, _)| other == *location)
+    }
+    //}}}
+
     //{{{ get_distance
     pub fn get_distance(
         &self,
         start: &(usize, usize),
         end: &(usize, usize),
-    ) -> u8 {
+    ) -> usize {
         let dist_x = (start.0).max(end.0) - (start.0).min(end.0);
         let dist_y = (start.1).max(end.1) - (start.1).min(end.1);
-        ((dist_x.pow(2) + dist_y.pow(2)) as f32).sqrt().ceil() as u8
+        ((dist_x.pow(2) + dist_y.pow(2)) as f32).sqrt().ceil() as usize
     }
     //}}}
 
@@ -331,8 +718,32 @@ impl Map {
         &self,
         start: &(usize, usize),
         end: &(usize, usize),
-    ) -> Option<u8> {
-        let maybe_path = astar(
+    ) -> Option<usize> {
+        self.find_path(start, end).map(|(_path, cost)| cost)
+    }
+    //}}}
+
+    //{{{ next_step
+    /*
+     * First step of the shortest path, None when there is no path
+     * or the start is the end already
+     */
+    pub fn next_step(
+        &self,
+        start: &(usize, usize),
+        end: &(usize, usize),
+    ) -> Option<(usize, usize)> {
+        self.find_path(start, end)
+            .and_then(|(path, _cost)| path.get(1).cloned())
+    }
+    //}}}
+
+    fn find_path(
+        &self,
+        start: &(usize, usize),
+        end: &(usize, usize),
+    ) -> Option<(Vec<(usize, usize)>, usize)> {
+        astar(
             start,
             |location| {
                 let mut neighbours = self.get_neighbours(location);
@@ -344,11 +755,8 @@ impl Map {
             },
             |location| self.get_distance(location, end),
             |location| *location == *end,
-        );
-
-        maybe_path.map(|(_path, cost)| cost)
+        )
     }
-    //}}}
 
     //{{{ reachable?
     pub fn reachable(
@@ -365,6 +773,10 @@ impl Map {
 
     //{{{ toggle_mark
     pub fn toggle_mark(&mut self, x: usize, y: usize) {
+        // Clicks beside a small map
+        if x >= self.width || y >= self.height {
+            return;
+        }
         let new_mark = (x, y);
         let mark_index = self.marks.binary_search(&new_mark);
         match mark_index {
@@ -376,84 +788,213 @@ impl Map {
     }
     //}}}
 
+    //{{{ ascii
+    /*
+     * Text picture of the map, one row per line, with the glyphs
+     * of the card files extended:
+     * '#' wall, '_' floor, 'O' obstacle, 'S' start, 'o' oil,
+     * 'C' children, 'L' lair, 'B' body, 'I' item, 'R' rest.
+     * Marks, visibility and obstacle messages are not kept.
+     */
+    pub fn to_ascii(&self, oil: &[(usize, usize)]) -> String {
+        let (width, height) = (self.width, self.height);
+        let start = self.get_location("start");
+
+        let mut text = String::with_capacity((width + 1) * height);
+        for y in 0..height {
+            for x in 0..width {
+                let tile = &self.tiles[x][y];
+                text.push(match tile.ttype {
+                    TileType::Wall => '#',
+                    TileType::Obstacle => 'O',
+                    TileType::Curiosity => {
+                        match EndType::from_scene(&tile.search_text) {
+                            Some(end_type) => end_type.glyph(),
+                            None => '_',
+                        }
+                    }
+                    TileType::Floor => if start == Some((x, y)) {
+                        'S'
+                    } else if oil.contains(&(x, y)) {
+                        'o'
+                    } else {
+                        '_'
+                    },
+                });
+            }
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /*
+     * Reverse of `to_ascii`, oil locations are returned along the map
+     */
+    pub fn from_ascii(text: &str) -> Result<(Map, Vec<(usize, usize)>)> {
+        let rows: Vec<&str> = text
+            .lines()
+            .map(|row| row.trim_end())
+            .filter(|row| !row.is_empty())
+            .collect();
+        let width = match rows.first() {
+            Some(row) => row.chars().count(),
+            None => {
+                return Err(Error::BadAsciiMap(String::from("map is empty")))
+            }
+        };
+
+        let mut map = Map {
+            tiles: vec![Vec::with_capacity(rows.len()); width],
+            width,
+            height: rows.len(),
+            marks: Vec::new(),
+            special_locations: HashMap::with_capacity(ENDS_COUNT),
+            slots: Vec::new(),
+        };
+        let mut oil: Vec<(usize, usize)> = Vec::new();
+
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(Error::BadAsciiMap(format!(
+                    "row {} is not {} tiles wide",
+                    y + 1,
+                    width
+                )));
+            }
+
+            for (x, glyph) in row.chars().enumerate() {
+                let tile = match glyph {
+                    '#' => Tile::init_regular(TileType::Wall).unwrap(),
+                    '_' | 'S' | 'o' => {
+                        Tile::init_regular(TileType::Floor).unwrap()
+                    }
+                    'O' => {
+                        let mut tile =
+                            Tile::init_regular(TileType::Obstacle).unwrap();
+                        // Texts are not kept in ASCII maps,
+                        // so every obstacle gets the first one
+                        tile.search_text = String::from("obstacle1");
+                        tile
+                    }
+                    _ => match EndType::from_glyph(glyph) {
+                        Some(end_type) => Tile::init_curio(end_type).unwrap(),
+                        None => {
+                            return Err(Error::BadAsciiMap(format!(
+                                "unknown glyph '{}' at {}, {}",
+                                glyph, x, y
+                            )))
+                        }
+                    },
+                };
+                match glyph {
+                    'S' => {
+                        map.special_locations
+                            .insert(String::from("start"), (x, y));
+                    }
+                    'o' => oil.push((x, y)),
+                    _ => if let TileType::Curiosity = tile.ttype {
+                        map.special_locations
+                            .insert(tile.search_text.clone(), (x, y));
+                    },
+                }
+                map.tiles[x].push(tile);
+            }
+        }
+
+        Ok((map, oil))
+    }
+    //}}}
+
     //}}}
 
     //{{{ add_obstacles
-    fn add_obstacles(&mut self) {
-        let mut rng = StdRng::new().expect("Failed to initialize randomness");
+    fn add_obstacles(
+        &mut self,
+        messages: &[String],
+        max_obstacles: u8,
+        rng: &mut GameRng,
+    ) {
+        // Hand-placed ones only need their messages
+        let fixed_obstacles = self.slots(Slot::Obstacle);
+        for &(x, y) in fixed_obstacles.iter() {
+            self.tiles[x][y] = Tile::init_obstacle(messages, rng);
+        }
 
         let mut possible_locations: Vec<(usize, usize)> = Vec::new();
-        let map_side = self.tiles.len();
-        for x in 0..map_side {
-            for y in 0..map_side {
+        for x in 0..self.width {
+            for y in 0..self.height {
                 if let TileType::Floor = self.tiles[x][y].ttype {
-                    possible_locations.push((x, y));
+                    if !self.has_slot(&(x, y)) {
+                        possible_locations.push((x, y));
+                    }
                 }
             }
         }
         rng.shuffle(&mut possible_locations);
 
-        let mut max_obstacles: u8 = match get_setting("obstacle_max") {
-            Some(value) => value,
-            None => 6,
-        };
-        max_obstacles = rng.gen_range(1, max_obstacles);
-
-        let mut db_path = PathBuf::from(".");
-        db_path.push(DB_FILENAME);
-
-        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-        let db_conn = Connection::open_with_flags(&db_path, flags).unwrap();
+        let max_obstacles: u8 = rng
+            .gen_range(1, max_obstacles)
+            .saturating_sub(fixed_obstacles.len() as u8);
 
         for _obstacle in 0..max_obstacles {
             if let Some((x, y)) = possible_locations.pop() {
-                let obstacle_tile =
-                    Tile::init_regular(TileType::Obstacle, &db_conn, &mut rng)
-                        .unwrap();
-                self.tiles[x][y] = obstacle_tile;
+                self.tiles[x][y] = Tile::init_obstacle(messages, rng);
+            }
+        }
+    }
+    //}}}
+
+    //{{{ resolve_maybe_walls
+    fn resolve_maybe_walls(&mut self, rng: &mut GameRng) {
+        for (x, y) in self.slots(Slot::MaybeWall) {
+            if rng.gen() {
+                self.tiles[x][y] = Tile::init_regular(TileType::Floor).unwrap();
             }
         }
     }
     //}}}
 
     //{{{ add_curio
-    fn add_curio(&mut self) -> Result<(), &str> {
+    fn add_curio(
+        &mut self,
+        rng: &mut GameRng,
+    ) -> ::std::result::Result<(), String> {
         // Finding dead ends
         let mut possible_locations: Vec<(usize, usize)> =
-            Vec::with_capacity(CARDS_MAP_SIDE * 2 * 4);
-        let max_coord = self.tiles.len();
-        for y in 1..max_coord {
-            // Top row
+            Vec::with_capacity((self.width + self.height) * 2);
+        let (last_x, last_y) = (self.width - 1, self.height - 1);
+        for y in 1..self.height {
+            // Left column
             if let TileType::Floor = self.tiles[0][y].ttype {
                 possible_locations.push((0, y));
             }
-            // Bottom row
-            if let TileType::Floor = self.tiles[max_coord - 1][y].ttype {
-                possible_locations.push((max_coord - 1, y));
+            // Right column
+            if let TileType::Floor = self.tiles[last_x][y].ttype {
+                possible_locations.push((last_x, y));
             }
         }
-        for x in 1..max_coord {
+        for x in 1..self.width {
             // Top row
             if let TileType::Floor = self.tiles[x][0].ttype {
                 possible_locations.push((x, 0));
             }
             // Bottom row
-            if let TileType::Floor = self.tiles[x][max_coord - 1].ttype {
-                possible_locations.push((x, max_coord - 1));
+            if let TileType::Floor = self.tiles[x][last_y].ttype {
+                possible_locations.push((x, last_y));
             }
         }
-
-        let mut rng = match StdRng::new() {
-            Ok(generator) => generator,
-            Err(_) => return Err("Failed to read system randomness"),
-        };
+        // Hand-placed spots are not dead ends
+        possible_locations.retain(|location| !self.has_slot(location));
 
         // Start location
         let start_index: usize;
         if possible_locations.len() > 0 {
             start_index = rng.gen_range(0, possible_locations.len());
         } else {
-            return Err("Not enough locations to even place one");
+            return Err(String::from(
+                "Not enough locations to even place one",
+            ));
         }
 
         let start_location = possible_locations.remove(start_index);
@@ -463,6 +1004,9 @@ impl Map {
         // Only reachable from the starting point should remain
         possible_locations
             .retain(|location| self.reachable(&start_location, location));
+        let mut curio_slots = self.slots(Slot::Curio);
+        curio_slots
+            .retain(|location| self.reachable(&start_location, location));
 
         let mut ends = [
             Tile::init_curio(EndType::Children).unwrap(),
@@ -472,12 +1016,21 @@ impl Map {
             Tile::init_curio(EndType::Rest).unwrap(),
         ];
 
-        if possible_locations.len() < ends.len() {
-            return Err("Not enough reachable locations");
+        if curio_slots.len() + possible_locations.len() < ends.len() {
+            return Err(format!(
+                "Not enough reachable locations: {} of {}",
+                curio_slots.len() + possible_locations.len(),
+                ends.len()
+            ));
         }
 
+        rng.shuffle(&mut curio_slots);
         rng.shuffle(&mut possible_locations);
         rng.shuffle(&mut ends);
+        // Hand-placed slots are taken first
+        let slots_count = curio_slots.len();
+        curio_slots.extend(possible_locations);
+        let possible_locations = curio_slots;
 
         for (&(x, y), end) in possible_locations.iter().zip(ends.iter()) {
             self.tiles[x][y] = end.clone();
@@ -485,17 +1038,11 @@ impl Map {
                 .insert(end.search_text.clone(), (x, y));
         }
 
-        // Closing the breaches
-        let mut db_path = PathBuf::from(".");
-        db_path.push(DB_FILENAME);
-
-        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-        let db_conn = Connection::open_with_flags(&db_path, flags).unwrap();
-
-        for location in possible_locations[ends.len()..].iter() {
+        // Closing the breaches, unused slots stay floors
+        let first_unused = ends.len().max(slots_count);
+        for location in possible_locations[first_unused..].iter() {
             let &(x, y) = location;
-            self.tiles[x][y] =
-                Tile::init_regular(TileType::Wall, &db_conn, &mut rng).unwrap();
+            self.tiles[x][y] = Tile::init_regular(TileType::Wall).unwrap();
         }
 
         Ok(())
@@ -507,67 +1054,238 @@ impl Map {
 /*
  * Basic initialization of cards is handled by this function
  */
-fn init_cards() -> Result<Vec<Card>, ()> {
-    // Generic query for retrieving rowids of cards
-    let query = String::from("select rowid from ") + DB_TABLE_W_CARDS + ";";
-
-    let mut db_path = PathBuf::from(".");
-    db_path.push(DB_FILENAME);
-
-    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-    match Connection::open_with_flags(&db_path, flags) {
-        Ok(db_connection) => {
-            match db_connection.prepare(&query) {
-                Ok(mut query_statement) => {
-                    // Retrieving cards
-                    let field_cards: Vec<Card> = query_statement
-                        .query_map(&[], |row| {
-                            let rowid: i64 = row.get(0);
-                            Card::new(rowid, &db_connection).unwrap()
-                        })
-                        .unwrap()
-                        .map(|row| row.unwrap())
-                        .collect();
+fn init_cards(
+    assets: &AssetSource,
+    cards_needed: usize,
+) -> Result<Vec<Card>> {
+    let card_assets = assets.load(AssetKind::Card)?;
+
+    if card_assets.len() < cards_needed {
+        return Err(Error::TooFewCards {
+            needed: cards_needed,
+            found: card_assets.len(),
+        });
+    }
 
-                    Ok(field_cards)
-                }
-                Err(_) => return Err(()),
+    let (cards, errors) = read_cards(&card_assets);
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(cards),
+    }
+}
+
+/*
+ * Every good card, and every problem of the others.
+ * Cards are laid side by side, so they should be of one size,
+ * the size of the first good card.
+ */
+pub fn read_cards(card_assets: &[Asset]) -> (Vec<Card>, Vec<Error>) {
+    let mut cards: Vec<Card> = Vec::with_capacity(card_assets.len());
+    let mut errors: Vec<Error> = Vec::new();
+    for asset in card_assets.iter() {
+        let mut card = match Card::new(&asset.name, &asset.bytes) {
+            Ok(card) => card,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+        card.fixed = asset.fixed;
+
+        if let Some(first) = cards.first() {
+            if first.side() != card.side() {
+                errors.push(Error::BadCardLayout {
+                    card: asset.name.clone(),
+                    reason: format!(
+                        "card side is {}, other cards have {}",
+                        card.side(),
+                        first.side()
+                    ),
+                });
+                continue;
             }
         }
-        Err(_) => return Err(()),
+        cards.push(card);
     }
+
+    (cards, errors)
 }
 //}}}
 
 /*
- * This function generates random field of cards
+ * This function generates random field of cards.
+ * Cards are shuffled, and every card is also rotated or flipped
+ * at random, unless it is fixed. Then they are placed so that
+ * the openings on the sides of neighbouring cards line up.
+ *
+ * None when no such placement was found.
  */
-fn generate_field<'a, S, T: SeedableRng<S>>(
+fn generate_field<T: Rng>(
     fields: &Vec<Card>,
-    random_number_generator: &'a mut T,
-) -> Vec<Vec<Card>> {
-    let mut mut_fields = fields.clone();
-    random_number_generator.shuffle(&mut mut_fields);
+    field_width: usize,
+    field_height: usize,
+    random_number_generator: &mut T,
+) -> Option<Vec<Vec<Card>>> {
+    let mut order: Vec<usize> = (0..fields.len()).collect();
+    random_number_generator.shuffle(&mut order);
+
+    let mut candidates: Vec<Candidate> = Vec::with_capacity(fields.len());
+    for &card_n in order.iter() {
+        // Drawn for fixed cards too, so fixing one card
+        // does not change the others
+        let first_transform: usize = random_number_generator.gen_range(0, 8);
+        candidates.push(Candidate::new(&fields[card_n], first_transform));
+    }
 
-    let cardfield_side: usize = CARDS_MAP_SIDE;
+    let mut used: Vec<bool> = vec![false; candidates.len()];
+    let mut placed: Vec<(usize, usize)> =
+        Vec::with_capacity(field_width * field_height);
+    let mut steps_left: usize = FIELD_MAX_STEPS;
+    if !place_cards(
+        &candidates,
+        (field_width, field_height),
+        &mut used,
+        &mut placed,
+        &mut steps_left,
+    ) {
+        return None;
+    }
 
-    // Row of columns
-    let mut cardfield: Vec<Vec<Card>> = Vec::with_capacity(cardfield_side);
+    // Row of columns, placed column by column
+    let cardfield: Vec<Vec<Card>> = placed
+        .chunks(field_height)
+        .map(|column| {
+            column
+                .iter()
+                .map(|&(card_n, orientation_n)| {
+                    candidates[card_n].orientations[orientation_n].0.clone()
+                })
+                .collect()
+        })
+        .collect();
+
+    Some(cardfield)
+}
+
+// Passability of the four sides, in the order of `Side`
+type Edges = [Vec<bool>; 4];
+
+/*
+ * A card in every orientation it may take, in the order they
+ * are tried. Orientations with the same sides as an earlier one
+ * are left out, they cannot fit anywhere the earlier one did not.
+ */
+struct Candidate {
+    orientations: Vec<(Card, Edges)>,
+    // Cards with equal sides are interchangeable
+    sides: Vec<Edges>,
+}
+impl Candidate {
+    fn new(card: &Card, first_transform: usize) -> Candidate {
+        let transforms: Vec<usize> = if card.fixed {
+            vec![0]
+        } else {
+            (0..8).map(|t| (first_transform + t) % 8).collect()
+        };
+
+        let mut orientations: Vec<(Card, Edges)> = Vec::with_capacity(8);
+        for transform in transforms {
+            let card = card.transformed(transform);
+            let edges: Edges = [
+                card.edge(Side::Top),
+                card.edge(Side::Right),
+                card.edge(Side::Bottom),
+                card.edge(Side::Left),
+            ];
+            if orientations.iter().all(|&(_, ref other)| *other != edges) {
+                orientations.push((card, edges));
+            }
+        }
+
+        let mut sides: Vec<Edges> =
+            orientations.iter().map(|&(_, ref edges)| edges.clone()).collect();
+        sides.sort();
+        Candidate {
+            orientations,
+            sides,
+        }
+    }
+}
+
+/*
+ * Backtracking over the cells of the field, column by column.
+ * A card fits when its left and top sides match the right
+ * and bottom sides of the cards already placed there.
+ */
+fn place_cards(
+    candidates: &[Candidate],
+    (field_width, field_height): (usize, usize),
+    used: &mut Vec<bool>,
+    placed: &mut Vec<(usize, usize)>,
+    steps_left: &mut usize,
+) -> bool {
+    let cell = placed.len();
+    if cell == field_width * field_height {
+        return true;
+    }
+    let (x, y) = (cell / field_height, cell % field_height);
+    let side_of = |cell: usize, side: Side| {
+        let (card_n, orientation_n) = placed[cell];
+        &candidates[card_n].orientations[orientation_n].1[side as usize]
+    };
+    let left: Option<Vec<bool>> = if x > 0 {
+        Some(side_of(cell - field_height, Side::Right).clone())
+    } else {
+        None
+    };
+    let top: Option<Vec<bool>> = if y > 0 {
+        Some(side_of(cell - 1, Side::Bottom).clone())
+    } else {
+        None
+    };
+
+    // Without it, every order of interchangeable cards is tried
+    let mut tried: Vec<usize> = Vec::new();
+    for (card_n, candidate) in candidates.iter().enumerate() {
+        if used[card_n]
+            || tried.iter().any(|&n| candidates[n].sides == candidate.sides)
+        {
+            continue;
+        }
+        tried.push(card_n);
 
-    // Direction is important because of Vec::push()
-    for x in 0..cardfield_side {
-        // Columns themselves
-        cardfield.push(Vec::with_capacity(cardfield_side));
+        for (orientation_n, &(_, ref edges)) in
+            candidate.orientations.iter().enumerate()
+        {
+            if *steps_left == 0 {
+                return false;
+            }
+            *steps_left -= 1;
+            let fits = left
+                .iter()
+                .all(|side| *side == edges[Side::Left as usize])
+                && top.iter().all(|side| *side == edges[Side::Top as usize]);
+            if !fits {
+                continue;
+            }
 
-        // Inserting cards
-        for _y in 0..cardfield_side {
-            if let Some(field) = mut_fields.pop() {
-                cardfield[x].push(field)
+            used[card_n] = true;
+            placed.push((card_n, orientation_n));
+            if place_cards(
+                candidates,
+                (field_width, field_height),
+                used,
+                placed,
+                steps_left,
+            ) {
+                return true;
             }
+            placed.pop();
+            used[card_n] = false;
         }
     }
 
-    cardfield
+    false
 }
 //}}}
 
@@ -575,32 +1293,39 @@ fn generate_field<'a, S, T: SeedableRng<S>>(
  * This funciton takes field of cards
  * and translates it into the game map
  */
-fn generate_map(cards_field: &Vec<Vec<Card>>) -> Vec<Vec<Tile>> {
-    // Cards are square and equal. This is a must.
+fn generate_map(
+    cards_field: &Vec<Vec<Card>>,
+) -> (Vec<Vec<Tile>>, Vec<((usize, usize), Slot)>) {
+    // Cards are square and equal, `init_cards` makes sure of it.
+    // The field itself may be wider than it is high, or the other way.
     let corner_card = &cards_field[0][0];
     let card_side = corner_card.tiles.len();
-    let field_side = cards_field.len();
+    let field_width = cards_field.len();
+    let field_height = cards_field[0].len();
 
     // Declare default tile
     let tile_wall = Tile {
         ttype: TileType::Wall,
         passable: false,
-        visible: false,
+        explored: false,
+        lit: false,
         curiosity_checked: false,
         search_text: String::from(""),
         icon: String::from("wall.png"),
     };
 
     // Now we can create our map
-    let map_side: usize = field_side * card_side;
+    let map_width: usize = field_width * card_side;
+    let map_height: usize = field_height * card_side;
 
     // Row of columns
-    let mut map: Vec<Vec<Tile>> = Vec::with_capacity(map_side);
+    let mut map: Vec<Vec<Tile>> = Vec::with_capacity(map_width);
+    let mut slots: Vec<((usize, usize), Slot)> = Vec::new();
 
     // Basic initialization
-    for _x in 0..map_side {
-        let mut column: Vec<Tile> = Vec::with_capacity(map_side);
-        for _y in 0..map_side {
+    for _x in 0..map_width {
+        let mut column: Vec<Tile> = Vec::with_capacity(map_height);
+        for _y in 0..map_height {
             column.push(tile_wall.clone());
         }
         map.push(column);
@@ -617,11 +1342,352 @@ fn generate_map(cards_field: &Vec<Vec<Card>>) -> Vec<Vec<Tile>> {
                     let tile_x = offset_x + x;
                     let tile_y = offset_y + y;
                     map[tile_x][tile_y] = tile.clone();
+                    if let Some(slot) = field.slots[x][y] {
+                        slots.push(((tile_x, tile_y), slot));
+                    }
                 }
             }
         }
     }
 
-    map
+    (map, slots)
 }
 //}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::assets::{DirectoryAssets, MemoryAssets};
+    use super::super::objects::{Kobold, Resources};
+    use super::super::testing::messages_db;
+
+    #[test]
+    fn card_from_text() {
+        let card = Card::from_text("1", "#_\n__\n").unwrap();
+        assert_eq!(card.tiles.len(), 2);
+        assert!(!card.tiles[0][0].passable);
+        assert!(card.tiles[1][0].passable);
+    }
+
+    #[test]
+    fn malformed_cards_are_errors() {
+        match Card::from_text("2", "") {
+            Err(Error::BadCardLayout { ref card, .. }) if card == "2" => (),
+            _ => panic!("empty card accepted"),
+        }
+        match Card::from_text("3", "#__\n__\n") {
+            Err(Error::BadCardLayout { ref card, .. }) if card == "3" => (),
+            _ => panic!("long line accepted"),
+        }
+        match Card::from_text("7", "#_\n_x\n") {
+            Err(Error::BadCardLayout { ref card, .. }) if card == "7" => (),
+            _ => panic!("unknown glyph accepted"),
+        }
+        match Card::from_text("9", "#_#\n_\n#_#\n") {
+            Err(Error::BadCardLayout { ref card, .. }) if card == "9" => (),
+            _ => panic!("short line accepted"),
+        }
+        match Card::from_text("10", "###\n#_#\n###\n") {
+            Err(Error::BadCardLayout { ref card, .. }) if card == "10" => (),
+            _ => panic!("closed card accepted"),
+        }
+    }
+
+    const SLOTS_CARD: &'static str = "\
+##_##_##
+#*____K#
+________
+#__O___#
+#__?___#
+________
+#o_____#
+##_##_##";
+
+    #[test]
+    fn cards_of_other_size_are_errors() {
+        let mut assets = MemoryAssets::new();
+        assets.insert(AssetKind::Card, "1", b"#_\n__".to_vec());
+        assets.insert(AssetKind::Card, "2", b"#_#\n___\n#_#".to_vec());
+        assets.insert(AssetKind::Card, "3", b"_#\n__".to_vec());
+
+        let card_assets = assets.load(AssetKind::Card).unwrap();
+        let (cards, errors) = read_cards(&card_assets);
+        let names: Vec<&str> = cards.iter().map(|card| card.name()).collect();
+        assert_eq!(names, ["1", "3"]);
+        match errors.as_slice() {
+            [Error::BadCardLayout { ref card, .. }] if card == "2" => (),
+            _ => panic!("{:?}", errors),
+        }
+    }
+
+    #[test]
+    fn card_slots() {
+        let card = Card::from_text("8", SLOTS_CARD).unwrap();
+        assert_eq!(card.slots[1][1], Some(Slot::Curio));
+        assert_eq!(card.slots[6][1], Some(Slot::KoboldSpawn));
+        assert_eq!(card.slots[1][6], Some(Slot::Oil));
+        assert_eq!(card.slots[3][3], Some(Slot::Obstacle));
+        assert!(!card.tiles[3][3].passable);
+        assert_eq!(card.slots[3][4], Some(Slot::MaybeWall));
+        assert!(!card.tiles[3][4].passable);
+        assert_eq!(card.slots[2][2], None);
+
+        // Slots turn along with the tiles
+        let turned = card.transformed(1);
+        assert_eq!(turned.slots[6][1], Some(Slot::Curio));
+        assert_eq!(turned.slots[6][6], Some(Slot::KoboldSpawn));
+    }
+
+    #[test]
+    fn card_slots_are_respected() {
+        let db_conn = messages_db(&[("obstacle1", "A crate")]);
+        let mut assets = MemoryAssets::new();
+        for card_n in 0..9 {
+            let name = card_n.to_string();
+            assets.insert(AssetKind::Card, &name, SLOTS_CARD.into());
+        }
+        let settings = Settings::default();
+        let mut rng = GameRng::new(2);
+        let map = Map::generate(&assets, &db_conn, &settings, &mut rng)
+            .unwrap();
+
+        // Fixed ones are more than the random ones could be
+        let obstacles = map.slots(Slot::Obstacle);
+        assert_eq!(obstacles.len(), 9);
+        for x in 0..map.width {
+            for y in 0..map.height {
+                if let TileType::Obstacle = map.tiles[x][y].ttype {
+                    assert!(obstacles.contains(&(x, y)));
+                    assert_eq!(map.tiles[x][y].search_text, "obstacle1");
+                }
+            }
+        }
+
+        let curio_slots = map.slots(Slot::Curio);
+        for end_type in EndType::ALL.iter() {
+            let location = map.get_location(end_type.scene()).unwrap();
+            assert!(curio_slots.contains(&location));
+        }
+
+        let resources = Resources::init(&map, &settings, &mut rng);
+        for location in map.slots(Slot::Oil).iter() {
+            assert!(resources.locations().contains(location));
+        }
+
+        let kobold = Kobold::init(&map, &settings, &mut rng);
+        assert!(map.slots(Slot::KoboldSpawn).contains(&(kobold.x, kobold.y)));
+    }
+
+    #[test]
+    fn kobold_spawns_within_reach() {
+        let rows = [
+            "#######", //
+            "#S____#", //
+            "#___###", //
+            "#___#_#", //
+            "#___###", //
+            "#####L#",
+        ];
+        let (mut map, _oil) = Map::from_ascii(&rows.join("\n")).unwrap();
+        map.slots = vec![
+            ((3, 1), Slot::KoboldSpawn),
+            ((5, 3), Slot::KoboldSpawn),
+        ];
+
+        for seed in 0..20 {
+            let mut rng = GameRng::new(seed);
+            let kobold = Kobold::init(&map, &Settings::default(), &mut rng);
+            assert_eq!((kobold.x, kobold.y), (3, 1));
+        }
+    }
+
+    fn card_text(card: &Card) -> String {
+        let side = card.tiles.len();
+        (0..side)
+            .map(|y| {
+                (0..side)
+                    .map(|x| if card.tiles[x][y].passable { '_' } else { '#' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn card_transforms() {
+        let card = Card::from_text("4", "##_\n___\n___\n").unwrap();
+        assert_eq!(card_text(&card.transformed(0)), "##_\n___\n___");
+        assert_eq!(card_text(&card.transformed(1)), "__#\n__#\n___");
+        assert_eq!(card_text(&card.transformed(4)), "_##\n___\n___");
+
+        let mut texts: Vec<String> =
+            (0..8).map(|t| card_text(&card.transformed(t))).collect();
+        texts.sort();
+        texts.dedup();
+        assert_eq!(texts.len(), 8);
+
+        let mut turned = card.clone();
+        for _turn in 0..4 {
+            turned = turned.transformed(1);
+        }
+        assert_eq!(card_text(&turned), card_text(&card));
+    }
+
+    #[test]
+    fn fixed_cards_are_not_transformed() {
+        let text = "#_##\n____\n#_##\n#_##";
+        let mut card = Card::from_text("5", text).unwrap();
+        assert!(card_text(&card.transformed(1)) != text);
+        card.fixed = true;
+        let cards = vec![card; 9];
+
+        let mut rng = GameRng::new(1);
+        for column in generate_field(&cards, 3, 3, &mut rng).unwrap().iter() {
+            for placed in column.iter() {
+                assert_eq!(card_text(placed), text);
+            }
+        }
+    }
+
+    #[test]
+    fn too_few_cards_are_counted() {
+        let assets = DirectoryAssets::new("data");
+        match init_cards(&assets, 20) {
+            Err(Error::TooFewCards { needed, found }) => {
+                assert_eq!((needed, found), (20, 18))
+            }
+            other => panic!("{:?} instead of TooFewCards", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn placed_cards_line_up() {
+        let assets = DirectoryAssets::new("data");
+        let cards = init_cards(&assets, 8).unwrap();
+
+        for seed in 0..20 {
+            let mut rng = GameRng::new(seed);
+            let field = generate_field(&cards, 4, 2, &mut rng).unwrap();
+            assert_eq!((field.len(), field[0].len()), (4, 2));
+            for x in 0..4 {
+                for y in 0..2 {
+                    let card = &field[x][y];
+                    if x > 0 {
+                        assert!(
+                            field[x - 1][y].edge(Side::Right)
+                                == card.edge(Side::Left)
+                        );
+                    }
+                    if y > 0 {
+                        assert!(
+                            field[x][y - 1].edge(Side::Bottom)
+                                == card.edge(Side::Top)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rectangular_maps() {
+        let db_conn = messages_db(&[("obstacle1", "")]);
+        let mut settings = Settings::default();
+        settings.field_width = 4;
+        settings.field_height = 2;
+
+        let map = Map::init_with_seed(
+            1,
+            &DirectoryAssets::new("data"),
+            &db_conn,
+            &settings,
+        ).unwrap();
+        assert_eq!((map.width, map.height), (32, 16));
+        assert_eq!((map.tiles.len(), map.tiles[0].len()), (32, 16));
+        assert!(map.get_neighbours(&(31, 15)).len() == 3);
+    }
+
+    #[test]
+    fn long_paths_are_measured() {
+        let wall = "#".repeat(302);
+        let corridor = String::from("#S") + &"_".repeat(298) + "C#";
+        let rows = [wall.clone(), corridor, wall];
+        let (map, _oil) = Map::from_ascii(&rows.join("\n")).unwrap();
+
+        assert_eq!(map.get_distance(&(1, 1), &(300, 1)), 299);
+        assert_eq!(map.get_path_distance(&(1, 1), &(300, 1)), Some(299));
+    }
+
+    #[test]
+    fn cards_which_cannot_fit_are_none() {
+        // Openings on the right and at the bottom only
+        let mut card = Card::from_text("6", "##\n#_").unwrap();
+        card.fixed = true;
+        let cards = vec![card; 9];
+
+        assert!(generate_field(&cards, 3, 3, &mut GameRng::new(1)).is_none());
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        let text = "\
+########
+#S_O_oL#
+#B_#_I_#
+#__o_R_C
+########
+";
+        let (map, oil) = Map::from_ascii(text).unwrap();
+
+        assert_eq!(map.tiles.len(), 8);
+        assert_eq!(map.get_location("start"), Some((1, 1)));
+        assert_eq!(map.get_location("children"), Some((7, 3)));
+        assert_eq!(oil, vec![(5, 1), (3, 3)]);
+        assert_eq!(map.to_ascii(&oil), text);
+    }
+
+    #[test]
+    fn lit_and_explored_tiles() {
+        let text = "\
+########
+#S_#___#
+#__#___#
+#______C
+########
+";
+        let (mut map, _oil) = Map::from_ascii(text).unwrap();
+        assert!(!map.is_lit(&(1, 1)));
+
+        map.update(&Player::init(1, 1, &Settings::default()));
+        assert!(map.is_lit(&(2, 2)));
+        assert!(map.is_lit(&(3, 1)));
+        assert!(!map.is_lit(&(5, 1)));
+        assert!(!map.tiles[5][1].explored);
+
+        // Seen tiles stay explored once the player walks away
+        map.update(&Player::init(5, 3, &Settings::default()));
+        assert!(map.is_lit(&(5, 1)));
+        assert!(!map.is_lit(&(1, 1)));
+        assert!(map.tiles[1][1].explored);
+
+        // Nothing is lit with the lamp out, explored tiles are kept
+        let mut player = Player::init(5, 3, &Settings::default());
+        while player.get_view_distance() > 0 {
+            player.drain_view_resource();
+        }
+        map.update(&player);
+        assert!(!map.is_lit(&(5, 3)));
+        assert!(map.tiles[5][3].explored);
+    }
+
+    #[test]
+    fn malformed_ascii_maps_are_errors() {
+        for text in ["", "#_\n#\n", "#_?\n"].iter() {
+            match Map::from_ascii(text) {
+                Err(Error::BadAsciiMap(_)) => (),
+                _ => panic!("{:?} accepted", text),
+            }
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use sdl2::EventSubsystem;
-use sdl2::keyboard::Keycode;
-use rand::{thread_rng, Rng, StdRng};
+use rand::Rng;
+use rusqlite::Connection;
 
-use super::map::Map;
+use super::map::{Map, Slot};
 use super::map::TileType;
-use super::get_setting;
+use super::game::Command;
+use super::events::{EventQueue, GameEvent};
+use super::GameRng;
+use super::settings::Settings;
+use super::save::{get_value, set_value};
+use super::Error;
 
 //{{{ Player
 pub struct Player {
@@ -20,26 +24,17 @@ pub struct Player {
     pub y: usize,
 }
 impl Player {
-    pub fn init(start_x: usize, start_y: usize) -> Player //{{{
+    pub fn init(
+        start_x: usize,
+        start_y: usize,
+        settings: &Settings,
+    ) -> Player //{{{
     {
-        // Default values
         let mut player = Player {
-            view_distance: match get_setting("visible_distance") {
-                Some(value) => value,
-                None => 5,
-            },
-            view_distance_danger: match get_setting("visible_distance_danger") {
-                Some(value) => value,
-                None => 2,
-            },
-            view_resource_max: match get_setting("resource_max") {
-                Some(value) => value,
-                None => 10,
-            },
-            view_resource_count: match get_setting("resource_start") {
-                Some(value) => value,
-                None => 3,
-            },
+            view_distance: settings.visible_distance,
+            view_distance_danger: settings.visible_distance_danger,
+            view_resource_max: settings.resource_max,
+            view_resource_count: settings.resource_start,
             view_resource: 0,
             in_danger: false,
             x: start_x,
@@ -51,6 +46,33 @@ impl Player {
     }
     //}}}
 
+    pub fn save(&self, save_conn: &Connection) -> super::Result<()> //{{{
+    {
+        set_value(save_conn, "player_x", self.x as i64)?;
+        set_value(save_conn, "player_y", self.y as i64)?;
+        set_value(save_conn, "player_oil", self.view_resource)?;
+        set_value(save_conn, "player_oil_count", self.view_resource_count)?;
+        set_value(save_conn, "player_in_danger", self.in_danger)?;
+        Ok(())
+    }
+    //}}}
+
+    pub fn load(
+        save_conn: &Connection,
+        settings: &Settings,
+    ) -> super::Result<Player> //{{{
+    {
+        let x: i64 = get_value(save_conn, "player_x")?;
+        let y: i64 = get_value(save_conn, "player_y")?;
+        let mut player = Player::init(x as usize, y as usize, settings);
+        player.view_resource = get_value(save_conn, "player_oil")?;
+        player.view_resource_count =
+            get_value(save_conn, "player_oil_count")?;
+        player.in_danger = get_value(save_conn, "player_in_danger")?;
+        Ok(player)
+    }
+    //}}}
+
     pub fn get_view_distance(&self) -> u8 //{{{
     {
         if self.view_resource > 0 {
@@ -65,6 +87,10 @@ impl Player {
     }
     //}}}
 
+    pub fn is_in_danger(&self) -> bool {
+        self.in_danger
+    }
+
     pub fn get_resource_state(&self) -> f32 //{{{
     {
         self.view_resource as f32 / self.view_resource_max as f32
@@ -106,7 +132,8 @@ impl Player {
         y_mod: isize,
         map: &Map,
     ) -> Result<(), (usize, usize)> {
-        let map_size = map.tiles.len() as isize - 1;
+        let last_x = map.width as isize - 1;
+        let last_y = map.height as isize - 1;
         let mut new_x: isize = self.x as isize + x_mod;
         let mut new_y: isize = self.y as isize + y_mod;
 
@@ -116,11 +143,11 @@ impl Player {
         if new_y < 0 {
             new_y = 0
         };
-        if new_x >= map_size {
-            new_x = map_size
+        if new_x >= last_x {
+            new_x = last_x
         };
-        if new_y >= map_size {
-            new_y = map_size
+        if new_y >= last_y {
+            new_y = last_y
         };
         let new_x: usize = new_x as usize;
         let new_y: usize = new_y as usize;
@@ -137,11 +164,11 @@ impl Player {
 
     pub fn update(
         &mut self,
-        key: &Keycode,
+        command: &Command,
         map: &Map,
         monster: &Kobold,
         resources: &Resources,
-        event_system: &EventSubsystem,
+        events: &mut EventQueue,
     ) -> bool {
         let mut updated: bool = false;
 
@@ -150,43 +177,20 @@ impl Player {
         let previous_view_resource = self.view_resource;
         let mut move_result: Option<Result<(), (usize, usize)>> = None;
 
-        //{{{ Reaction to keypresses
-        match *key {
+        //{{{ Reaction to commands
+        match *command {
             // Movement
-            Keycode::Up | Keycode::Kp8 | Keycode::Num8 | Keycode::W => {
-                move_result = Some(self.move_relative(0, -1, map))
-            }
-            Keycode::Down | Keycode::Kp2 | Keycode::Num2 | Keycode::S => {
-                move_result = Some(self.move_relative(0, 1, map))
-            }
-            Keycode::Left | Keycode::Kp4 | Keycode::Num4 | Keycode::A => {
-                move_result = Some(self.move_relative(-1, 0, map))
-            }
-            Keycode::Right | Keycode::Kp6 | Keycode::Num6 | Keycode::D => {
-                move_result = Some(self.move_relative(1, 0, map))
-            }
-            Keycode::Kp1 | Keycode::Num1 | Keycode::Z => {
-                move_result = Some(self.move_relative(-1, 1, map))
-            }
-            Keycode::Kp3 | Keycode::Num3 | Keycode::C => {
-                move_result = Some(self.move_relative(1, 1, map))
-            }
-            Keycode::Kp7 | Keycode::Num7 | Keycode::Q => {
-                move_result = Some(self.move_relative(-1, -1, map))
-            }
-            Keycode::Kp9 | Keycode::Num9 | Keycode::E => {
-                move_result = Some(self.move_relative(1, -1, map))
+            Command::Move(x_mod, y_mod) => {
+                move_result = Some(self.move_relative(x_mod, y_mod, map))
             }
 
             // Actions
-            Keycode::R | Keycode::Kp0 | Keycode::Num0 => {
-                let refill_result_event = EventResourceRefill {
-                    success: match self.refill_view_resource() {
-                        Ok(_) => true,
-                        Err(_) => false,
-                    },
+            Command::Refill => {
+                let success = match self.refill_view_resource() {
+                    Ok(_) => true,
+                    Err(_) => false,
                 };
-                event_system.push_custom_event(refill_result_event).unwrap();
+                events.push(GameEvent::ResourceRefill { success });
                 updated = true;
             }
 
@@ -204,22 +208,20 @@ impl Player {
 
                     if let TileType::Curiosity = map.tiles[self.x][self.y].ttype
                     {
-                        let curio_found = EventCurioFound {
+                        events.push(GameEvent::CurioFound {
                             scene: map.tiles[self.x][self.y]
                                 .search_text
                                 .clone(),
-                        };
-                        event_system.push_custom_event(curio_found).unwrap();
+                        });
                     }
 
                     updated = true;
                 }
                 Err((x, y)) => {
                     if let TileType::Obstacle = map.tiles[x][y].ttype {
-                        let obstacle_found = EventObstacleFound {
+                        events.push(GameEvent::ObstacleFound {
                             text: map.tiles[x][y].search_text.clone(),
-                        };
-                        event_system.push_custom_event(obstacle_found).unwrap();
+                        });
                         updated = true;
                     }
                 }
@@ -232,39 +234,34 @@ impl Player {
             let self_loc = (self.x, self.y);
             let monster_loc = (monster.x, monster.y);
 
-            if map.get_distance(&self_loc, &monster_loc)
-                < monster.danger_distance
-            {
+            let danger_distance = monster.danger_distance as usize;
+            if map.get_distance(&self_loc, &monster_loc) < danger_distance {
                 if let Some(dist) =
                     map.get_path_distance(&self_loc, &monster_loc)
                 {
-                    if dist < monster.danger_distance {
+                    if dist < danger_distance {
                         self.in_danger = true;
-                        let in_danger = EventPlayerInDanger {};
-                        event_system.push_custom_event(in_danger).unwrap();
+                        events.push(GameEvent::PlayerInDanger);
                     }
                 }
             }
 
             // Monster met?
             if self_loc == monster_loc {
-                let meet_monster = EventPlayerMeetMonster {};
-                event_system.push_custom_event(meet_monster).unwrap();
+                events.push(GameEvent::PlayerMeetMonster);
             }
         }
 
         // Was resource found?
         for (i, &resource_location) in resources.locations.iter().enumerate() {
             if resource_location == (self.x, self.y) {
-                let resource_found = EventResourceFound { index: i };
-                event_system.push_custom_event(resource_found).unwrap();
+                events.push(GameEvent::ResourceFound { index: i });
             }
         }
 
         // Was resource gone?
         if previous_view_resource > 0 && self.view_resource == 0 {
-            let resource_gone = EventResourceGone {};
-            event_system.push_custom_event(resource_gone).unwrap();
+            events.push(GameEvent::ResourceGone);
         }
         //}}}
 
@@ -279,32 +276,47 @@ pub struct Resources {
     locations: Vec<(usize, usize)>,
 }
 impl Resources {
-    pub fn init(map: &Map, player: &Player) -> Resources {
-        let mut rng: StdRng =
-            StdRng::new().expect("Cannot read randomness from OS");
-        let sections_side: u32 = match get_setting("resource_distance") {
-            Some(value) => value,
-            None => player.view_resource_max as u32,
-        };
-        let sections_side: usize = sections_side as usize;
+    pub fn init(
+        map: &Map,
+        settings: &Settings,
+        rng: &mut GameRng,
+    ) -> Resources {
+        let sections_side: usize = settings.resource_distance as usize;
+        let oil_slots = map.slots(Slot::Oil);
         let mut locations: Vec<(usize, usize)> =
-            Vec::with_capacity(map.tiles.len() / sections_side ^ 2);
+            Vec::with_capacity(
+                (map.width / sections_side + 1)
+                    * (map.height / sections_side + 1),
+            );
 
         /*
          * Two 'while' cycles are needed to segregate map into sections
          * with sides equal to view_resource_max.
          */
         let mut x = 0;
-        while x < map.tiles.len() {
-            let max_x = (x + sections_side).min(map.tiles.len());
+        while x < map.width {
+            let max_x = (x + sections_side).min(map.width);
 
             let mut y = 0;
-            while y < map.tiles.len() {
-                let max_y = (y + sections_side).min(map.tiles.len());
+            while y < map.height {
+                let max_y = (y + sections_side).min(map.height);
 
                 /*
                  * Now we can do what's needs to be done in those sections
                  */
+                // Hand-placed oil takes the place of the random one
+                let in_section = |&&(slot_x, slot_y): &&(usize, usize)| {
+                    slot_x >= x && slot_x < max_x && slot_y >= y
+                        && slot_y < max_y
+                };
+                let fixed_oil: Vec<&(usize, usize)> =
+                    oil_slots.iter().filter(in_section).collect();
+                if !fixed_oil.is_empty() {
+                    locations.extend(fixed_oil);
+                    y += sections_side;
+                    continue;
+                }
+
                 let mut possible_locations: Vec<(usize, usize)> = Vec::new();
                 for inner_x in x..max_x {
                     for inner_y in y..max_y {
@@ -331,36 +343,141 @@ impl Resources {
         Resources { locations }
     }
 
-    pub fn process_event(&mut self, custom_event: &EventResourceFound) {
-        self.locations.remove(custom_event.index);
+    pub fn remove(&mut self, index: usize) {
+        self.locations.remove(index);
+    }
+
+    pub fn from_locations(locations: Vec<(usize, usize)>) -> Resources {
+        Resources { locations }
+    }
+
+    pub fn locations(&self) -> &[(usize, usize)] {
+        &self.locations
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn save(&self, save_conn: &Connection) -> super::Result<()> {
+        for &(x, y) in self.locations.iter() {
+            save_conn.execute(
+                "insert into resources(x, y) values (?, ?);",
+                &[&(x as i64), &(y as i64)],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn load(save_conn: &Connection) -> super::Result<Resources> {
+        let mut statement =
+            save_conn.prepare("select x, y from resources order by rowid;")?;
+        let locations: Vec<(usize, usize)> = statement
+            .query_map(&[], |row| {
+                let x: i64 = row.get(0);
+                let y: i64 = row.get(1);
+                (x as usize, y as usize)
+            })?
+            .collect::<::rusqlite::Result<Vec<(usize, usize)>>>()?;
+
+        Ok(Resources { locations })
     }
 }
 //}}}
 
 //{{{ Monster
+/*
+ * What the kobold is up to
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KoboldState {
+    // Roams around the place it started from
+    Wander,
+    // Goes to see where a noise or a glimmer of light came from
+    Investigate { target: (usize, usize) },
+    // Chases the player to where the player was last noticed,
+    // gives up after some turns without noticing the player again
+    Hunt {
+        last_seen: (usize, usize),
+        lost_turns: u8,
+    },
+}
+impl KoboldState {
+    fn name(&self) -> &'static str {
+        match *self {
+            KoboldState::Wander => "wander",
+            KoboldState::Investigate { .. } => "investigate",
+            KoboldState::Hunt { .. } => "hunt",
+        }
+    }
+
+    // Where the kobold heads to, if anywhere
+    fn target(&self) -> Option<(usize, usize)> {
+        match *self {
+            KoboldState::Wander => None,
+            KoboldState::Investigate { target } => Some(target),
+            KoboldState::Hunt { last_seen, .. } => Some(last_seen),
+        }
+    }
+}
+
 pub struct Kobold {
     alive: bool,
     danger_distance: u8,
     pub x: usize,
     pub y: usize,
+    pub state: KoboldState,
+    home: (usize, usize),
+    // In the light after the last turn, and where it was last seen
+    // if it has just stepped out of the light, for drawing
+    seen: bool,
+    glimpse: Option<(usize, usize)>,
+
+    hear_distance: u8,
+    light_distance: u8,
+    track_turns: u8,
+    wander_distance: u8,
 }
 impl Kobold {
-    pub fn init(map: &Map) -> Kobold {
+    /*
+     * The kobold starts from one of the spawn points of the cards
+     * which the player can reach, or from its lair when there are none
+     */
+    pub fn init(map: &Map, settings: &Settings, rng: &mut GameRng) -> Kobold {
         // Default
         let mut kobold = Kobold {
             alive: true,
-            danger_distance: match get_setting("kobold_danger_dist") {
-                Some(value) => value,
-                None => 5,
-            } + 1,
+            danger_distance: settings.kobold_danger_dist + 1,
             x: 0,
             y: 0,
+            state: KoboldState::Wander,
+            home: (0, 0),
+            seen: false,
+            glimpse: None,
+            hear_distance: settings.kobold_hear_dist,
+            light_distance: settings.kobold_light_dist,
+            track_turns: settings.kobold_track_turns,
+            wander_distance: settings.kobold_wander_dist,
         };
 
-        match map.get_location("lair") {
+        // Walled off spots would keep the kobold away from the player
+        let mut spawn_points = map.slots(Slot::KoboldSpawn);
+        if let Some(start) = map.get_location("start") {
+            spawn_points.retain(|location| map.reachable(&start, location));
+        }
+        let spawn_point = match rng.choose(&spawn_points) {
+            Some(&location) => Some(location),
+            None => map.get_location("lair"),
+        };
+        match spawn_point {
             Some((x, y)) => {
                 kobold.x = x;
                 kobold.y = y;
+                kobold.home = (x, y);
             }
             None => kobold.alive = false,
         }
@@ -368,66 +485,535 @@ impl Kobold {
         kobold
     }
 
-    pub fn update(&mut self, map: &Map) {
-        if self.alive {
-            let mut possible_steps = map.get_neighbours(&(self.x, self.y));
-            possible_steps.retain(|location| {
-                let &((x, y), _) = location;
-                map.tiles[x][y].passable
-            });
-            let maybe_step = thread_rng().choose(&possible_steps);
-            if let Some(&((sx, sy), _)) = maybe_step {
-                self.x = sx;
-                self.y = sy;
+    /*
+     * Should go after the map update, the kobold sees the player
+     * when it stands in the light of the lamp
+     */
+    pub fn update(
+        &mut self,
+        map: &Map,
+        player: &Player,
+        rng: &mut GameRng,
+        events: &mut EventQueue,
+    ) {
+        if !self.alive {
+            return;
+        }
+
+        self.think(map, player);
+        let self_loc = (self.x, self.y);
+        let step = match self.state.target() {
+            Some(target) if target != self_loc => {
+                match map.next_step(&self_loc, &target) {
+                    Some(step) => Some(step),
+                    // Heard through the rock, but there is no way there
+                    None => {
+                        self.state = KoboldState::Wander;
+                        self.wander_step(map, rng)
+                    }
+                }
+            }
+            Some(_) => None,
+            None => self.wander_step(map, rng),
+        };
+
+        match step {
+            Some((x, y)) => {
+                self.x = x;
+                self.y = y;
+                events.push(GameEvent::MonsterStep { location: (x, y) });
+                // Caught the player, who stood still or came closer
+                if (x, y) == (player.x, player.y) {
+                    events.push(GameEvent::PlayerMeetMonster);
+                }
+            }
+            None => {
+                events.push(GameEvent::MonsterBreath { location: self_loc })
             }
         }
+
+        self.look(map);
+        if !self.seen && step.is_some() && map.is_lit(&self_loc) {
+            self.glimpse = Some(self_loc);
+        }
+    }
+
+    /*
+     * Whether the player sees the kobold now, after the map update
+     */
+    pub fn look(&mut self, map: &Map) {
+        self.seen = self.is_seen(map);
+        self.glimpse = None;
     }
 
+    //{{{ think
+    fn think(&mut self, map: &Map, player: &Player) {
+        let self_loc = (self.x, self.y);
+        let player_loc = (player.x, player.y);
+        let distance = map.get_distance(&self_loc, &player_loc);
+
+        let sees = map.is_lit(&self_loc);
+        let lamp_radius = player.get_view_distance() as usize;
+        let notices = distance <= self.hear_distance as usize
+            || (lamp_radius > 0
+                && distance <= lamp_radius + self.light_distance as usize);
+
+        self.state = match self.state {
+            _ if sees => KoboldState::Hunt {
+                last_seen: player_loc,
+                lost_turns: 0,
+            },
+            KoboldState::Hunt { .. } if notices => KoboldState::Hunt {
+                last_seen: player_loc,
+                lost_turns: 0,
+            },
+            KoboldState::Hunt {
+                last_seen,
+                lost_turns,
+            } => if lost_turns < self.track_turns {
+                KoboldState::Hunt {
+                    last_seen,
+                    lost_turns: lost_turns + 1,
+                }
+            } else {
+                KoboldState::Wander
+            },
+            _ if notices => KoboldState::Investigate { target: player_loc },
+            KoboldState::Investigate { target } if target != self_loc => {
+                KoboldState::Investigate { target }
+            }
+            KoboldState::Investigate { .. } | KoboldState::Wander => {
+                KoboldState::Wander
+            }
+        };
+    }
+    //}}}
+
+    //{{{ wander_step
+    /*
+     * A random step which keeps the kobold near its home.
+     * Once it is too far, after a hunt, it heads home.
+     */
+    fn wander_step(
+        &self,
+        map: &Map,
+        rng: &mut GameRng,
+    ) -> Option<(usize, usize)> {
+        let self_loc = (self.x, self.y);
+        let wander_distance = self.wander_distance as usize;
+        if map.get_distance(&self_loc, &self.home) > wander_distance {
+            return map.next_step(&self_loc, &self.home);
+        }
+
+        let mut possible_steps = map.get_neighbours(&self_loc);
+        possible_steps.retain(|location| {
+            let &((x, y), _) = location;
+            map.tiles[x][y].passable
+                && map.get_distance(&(x, y), &self.home) <= wander_distance
+        });
+        rng.choose(&possible_steps).map(|&(location, _)| location)
+    }
+    //}}}
+
     pub fn die(&mut self) {
         self.alive = false;
+        self.seen = false;
+        self.glimpse = None;
+    }
+
+    /*
+     * The player sees the kobold only in the light of the lamp
+     */
+    pub fn is_seen(&self, map: &Map) -> bool {
+        self.alive && map.is_lit(&(self.x, self.y))
+    }
+
+    // Where to draw the kobold, if the player sees it
+    pub fn seen_at(&self) -> Option<(usize, usize)> {
+        if self.seen {
+            Some((self.x, self.y))
+        } else {
+            None
+        }
+    }
+
+    // Where the kobold was seen right before it left the light
+    pub fn glimpse(&self) -> Option<(usize, usize)> {
+        self.glimpse
+    }
+
+    pub fn save(&self, save_conn: &Connection) -> super::Result<()> {
+        set_value(save_conn, "kobold_x", self.x as i64)?;
+        set_value(save_conn, "kobold_y", self.y as i64)?;
+        set_value(save_conn, "kobold_alive", self.alive)?;
+        set_value(save_conn, "kobold_home_x", self.home.0 as i64)?;
+        set_value(save_conn, "kobold_home_y", self.home.1 as i64)?;
+
+        set_value(save_conn, "kobold_state", self.state.name())?;
+        let (target_x, target_y) = self.state.target().unwrap_or(self.home);
+        set_value(save_conn, "kobold_target_x", target_x as i64)?;
+        set_value(save_conn, "kobold_target_y", target_y as i64)?;
+        let lost_turns = match self.state {
+            KoboldState::Hunt { lost_turns, .. } => lost_turns,
+            _ => 0,
+        };
+        set_value(save_conn, "kobold_lost_turns", lost_turns)?;
+        Ok(())
+    }
+
+    pub fn load(
+        save_conn: &Connection,
+        settings: &Settings,
+    ) -> super::Result<Kobold> {
+        let x: i64 = get_value(save_conn, "kobold_x")?;
+        let y: i64 = get_value(save_conn, "kobold_y")?;
+        let home_x: i64 = get_value(save_conn, "kobold_home_x")?;
+        let home_y: i64 = get_value(save_conn, "kobold_home_y")?;
+
+        let state_name: String = get_value(save_conn, "kobold_state")?;
+        let target_x: i64 = get_value(save_conn, "kobold_target_x")?;
+        let target_y: i64 = get_value(save_conn, "kobold_target_y")?;
+        let target = (target_x as usize, target_y as usize);
+        let state = match state_name.as_str() {
+            "wander" => KoboldState::Wander,
+            "investigate" => KoboldState::Investigate { target },
+            "hunt" => KoboldState::Hunt {
+                last_seen: target,
+                lost_turns: get_value(save_conn, "kobold_lost_turns")?,
+            },
+            _ => return Err(Error::corrupt_blob("game", "kobold_state")),
+        };
+
+        Ok(Kobold {
+            alive: get_value(save_conn, "kobold_alive")?,
+            danger_distance: settings.kobold_danger_dist + 1,
+            x: x as usize,
+            y: y as usize,
+            state,
+            home: (home_x as usize, home_y as usize),
+            seen: false,
+            glimpse: None,
+            hear_distance: settings.kobold_hear_dist,
+            light_distance: settings.kobold_light_dist,
+            track_turns: settings.kobold_track_turns,
+            wander_distance: settings.kobold_wander_dist,
+        })
     }
 }
 //}}}
 
+//{{{ place_objects
 /*
- * Custom events
+ * The player, the kobold and the oil of a new map. The order of
+ * the random decisions is a part of the seed, so kobald-mapgen
+ * places them here too.
  */
-pub struct EventResourceFound {
-    index: usize,
-}
-pub struct EventResourceGone {}
-pub struct EventResourceRefill {
-    pub success: bool,
-}
-pub struct EventObstacleFound {
-    pub text: String,
-}
-pub struct EventCurioFound {
-    pub scene: String,
+pub fn place_objects(
+    map: &Map,
+    settings: &Settings,
+    rng: &mut GameRng,
+) -> (Player, Kobold, Resources) {
+    let start = map.get_location("start").unwrap();
+    let player = Player::init(start.0, start.1, settings);
+    let monster = Kobold::init(map, settings, rng);
+    let resources = Resources::init(map, settings, rng);
+    (player, monster, resources)
 }
-pub struct EventPlayerInDanger {}
-pub struct EventPlayerMeetMonster {}
-
-pub fn init_custom_events(sdl_event: &EventSubsystem) {
-    sdl_event
-        .register_custom_event::<EventResourceFound>()
-        .expect("Failed to register event.");
-    sdl_event
-        .register_custom_event::<EventResourceGone>()
-        .expect("Failed to register event.");
-    sdl_event
-        .register_custom_event::<EventResourceRefill>()
-        .expect("Failed to register event.");
-    sdl_event
-        .register_custom_event::<EventObstacleFound>()
-        .expect("Failed to register event.");
-    sdl_event
-        .register_custom_event::<EventCurioFound>()
-        .expect("Failed to register event.");
-    sdl_event
-        .register_custom_event::<EventPlayerInDanger>()
-        .expect("Failed to register event.");
-    sdl_event
-        .register_custom_event::<EventPlayerMeetMonster>()
-        .expect("Failed to register event.");
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROWS: &[&str] = &[
+        "########",
+        "#S_O__L#",
+        "#__#___#",
+        "#______C",
+        "########",
+    ];
+
+    fn no_resources() -> Resources {
+        Resources {
+            locations: Vec::new(),
+        }
+    }
+
+    fn dead_kobold(map: &Map) -> Kobold {
+        let mut kobold =
+            Kobold::init(map, &Settings::default(), &mut GameRng::new(0));
+        kobold.die();
+        kobold
+    }
+
+    fn step(
+        player: &mut Player,
+        x_mod: isize,
+        y_mod: isize,
+        map: &Map,
+        monster: &Kobold,
+        resources: &Resources,
+    ) -> (bool, EventQueue) {
+        let mut events = EventQueue::new();
+        let updated = player.update(
+            &Command::Move(x_mod, y_mod),
+            map,
+            monster,
+            resources,
+            &mut events,
+        );
+        (updated, events)
+    }
+
+    #[test]
+    fn walls_stop_movement() {
+        let (map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let monster = dead_kobold(&map);
+        let mut player = Player::init(1, 1, &Settings::default());
+
+        let (updated, events) =
+            step(&mut player, -1, 0, &map, &monster, &no_resources());
+
+        assert!(!updated);
+        assert!(events.is_empty());
+        assert_eq!((player.x, player.y), (1, 1));
+    }
+
+    #[test]
+    fn obstacle_is_reported() {
+        let (map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let monster = dead_kobold(&map);
+        let mut player = Player::init(2, 1, &Settings::default());
+
+        let (updated, mut events) =
+            step(&mut player, 1, 0, &map, &monster, &no_resources());
+
+        assert!(updated);
+        assert_eq!((player.x, player.y), (2, 1));
+        assert_eq!(
+            events.pop(),
+            Some(GameEvent::ObstacleFound {
+                text: String::from("obstacle1"),
+            })
+        );
+    }
+
+    #[test]
+    fn curio_is_reported() {
+        let (map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let monster = dead_kobold(&map);
+        let mut player = Player::init(6, 3, &Settings::default());
+
+        let (_, events) =
+            step(&mut player, 1, 0, &map, &monster, &no_resources());
+
+        assert!(events.contains(&GameEvent::CurioFound {
+            scene: String::from("children"),
+        }));
+    }
+
+    #[test]
+    fn resource_is_found_and_lamp_goes_out() {
+        let (map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let monster = dead_kobold(&map);
+        let resources = Resources {
+            locations: vec![(5, 5), (2, 2)],
+        };
+        let mut player = Player::init(1, 1, &Settings::default());
+        player.view_resource = 1;
+
+        let (updated, events) =
+            step(&mut player, 1, 1, &map, &monster, &resources);
+
+        assert!(updated);
+        assert_eq!((player.x, player.y), (2, 2));
+        assert_eq!(player.get_view_distance(), 0);
+        assert!(events.contains(&GameEvent::ResourceFound { index: 1 }));
+        assert!(events.contains(&GameEvent::ResourceGone));
+    }
+
+    #[test]
+    fn kobold_nearby_is_dangerous() {
+        let (map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let mut monster =
+            Kobold::init(&map, &Settings::default(), &mut GameRng::new(0));
+        let mut player = Player::init(4, 2, &Settings::default());
+
+        let (_, events) =
+            step(&mut player, 1, 0, &map, &monster, &no_resources());
+        assert!(events.contains(&GameEvent::PlayerInDanger));
+        assert!(!events.contains(&GameEvent::PlayerMeetMonster));
+
+        monster.x = 6;
+        monster.y = 2;
+        let (_, events) =
+            step(&mut player, 1, 0, &map, &monster, &no_resources());
+        assert!(events.contains(&GameEvent::PlayerMeetMonster));
+    }
+
+    #[test]
+    fn kobold_is_seen_only_in_light() {
+        let (mut map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let mut monster =
+            Kobold::init(&map, &Settings::default(), &mut GameRng::new(0));
+        let mut player = Player::init(4, 2, &Settings::default());
+
+        map.update(&player);
+        assert!(monster.is_seen(&map));
+
+        player.view_resource = 0;
+        map.update(&player);
+        assert!(!monster.is_seen(&map));
+
+        map.update(&Player::init(4, 2, &Settings::default()));
+        monster.die();
+        assert!(!monster.is_seen(&map));
+    }
+
+    fn dark_player(x: usize, y: usize) -> Player {
+        let mut player = Player::init(x, y, &Settings::default());
+        player.view_resource = 0;
+        player
+    }
+
+    fn kobold_step(
+        monster: &mut Kobold,
+        map: &mut Map,
+        player: &Player,
+        rng: &mut GameRng,
+    ) -> EventQueue {
+        let mut events = EventQueue::new();
+        map.update(player);
+        monster.update(map, player, rng, &mut events);
+        events
+    }
+
+    #[test]
+    fn kobold_hunts_the_player_in_sight() {
+        let (mut map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let mut rng = GameRng::new(0);
+        let mut monster = Kobold::init(&map, &Settings::default(), &mut rng);
+        let player = Player::init(4, 2, &Settings::default());
+
+        kobold_step(&mut monster, &mut map, &player, &mut rng);
+        assert_eq!(
+            monster.state,
+            KoboldState::Hunt {
+                last_seen: (4, 2),
+                lost_turns: 0,
+            }
+        );
+        assert_eq!(map.get_distance(&(monster.x, monster.y), &(4, 2)), 1);
+        assert_eq!(monster.seen_at(), Some((monster.x, monster.y)));
+
+        let events = kobold_step(&mut monster, &mut map, &player, &mut rng);
+        assert_eq!((monster.x, monster.y), (4, 2));
+        assert!(events.contains(&GameEvent::PlayerMeetMonster));
+    }
+
+    #[test]
+    fn kobold_investigates_the_light() {
+        let (mut map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let mut rng = GameRng::new(0);
+        let mut monster = Kobold::init(&map, &Settings::default(), &mut rng);
+        let player = Player::init(1, 1, &Settings::default());
+
+        kobold_step(&mut monster, &mut map, &player, &mut rng);
+        assert!(!monster.is_seen(&map));
+        assert_eq!(
+            monster.state,
+            KoboldState::Investigate { target: (1, 1) }
+        );
+    }
+
+    #[test]
+    fn kobold_loses_track_in_the_dark() {
+        let (mut map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let settings = Settings::default();
+        let mut rng = GameRng::new(0);
+        let mut monster = Kobold::init(&map, &settings, &mut rng);
+        let player = dark_player(1, 3);
+        monster.state = KoboldState::Hunt {
+            last_seen: (4, 3),
+            lost_turns: 0,
+        };
+
+        for _ in 0..settings.kobold_track_turns {
+            kobold_step(&mut monster, &mut map, &player, &mut rng);
+        }
+        assert_eq!((monster.x, monster.y), (4, 3));
+        assert_eq!(
+            monster.state,
+            KoboldState::Hunt {
+                last_seen: (4, 3),
+                lost_turns: settings.kobold_track_turns,
+            }
+        );
+
+        kobold_step(&mut monster, &mut map, &player, &mut rng);
+        assert_eq!(monster.state, KoboldState::Wander);
+        assert_eq!(monster.seen_at(), None);
+        assert_eq!(monster.glimpse(), None);
+    }
+
+    #[test]
+    fn kobold_gives_up_unreachable_targets() {
+        let rows = [
+            "#######", //
+            "#S_#__#", //
+            "#__#_L#", //
+            "#######",
+        ];
+        let (mut map, _oil) = Map::from_ascii(&rows.join("\n")).unwrap();
+        let mut rng = GameRng::new(0);
+        let mut monster = Kobold::init(&map, &Settings::default(), &mut rng);
+        let player = dark_player(2, 2);
+        monster.state = KoboldState::Investigate { target: (2, 2) };
+
+        for _ in 0..5 {
+            kobold_step(&mut monster, &mut map, &player, &mut rng);
+            assert_eq!(monster.state, KoboldState::Wander);
+            assert!(monster.x >= 4);
+        }
+    }
+
+    #[test]
+    fn kobold_leaves_a_glimpse() {
+        let rows = [
+            "###########", //
+            "#S_______L#", //
+            "###########",
+        ];
+        let (mut map, _oil) = Map::from_ascii(&rows.join("\n")).unwrap();
+        // A chasm the light goes over, but the kobold cannot cross
+        map.tiles[5][1].passable = false;
+        let mut rng = GameRng::new(0);
+        let mut monster = Kobold::init(&map, &Settings::default(), &mut rng);
+        monster.x = 6;
+        let player = Player::init(1, 1, &Settings::default());
+
+        kobold_step(&mut monster, &mut map, &player, &mut rng);
+        assert_eq!((monster.x, monster.y), (7, 1));
+        assert_eq!(monster.seen_at(), None);
+        assert_eq!(monster.glimpse(), Some((6, 1)));
+
+        kobold_step(&mut monster, &mut map, &player, &mut rng);
+        assert_eq!(monster.glimpse(), None);
+    }
+
+    #[test]
+    fn kobold_wanders_near_home() {
+        let (mut map, _oil) = Map::from_ascii(&ROWS.join("\n")).unwrap();
+        let mut settings = Settings::default();
+        settings.kobold_wander_dist = 1;
+        let mut rng = GameRng::new(0);
+        let mut monster = Kobold::init(&map, &settings, &mut rng);
+        let player = dark_player(1, 3);
+
+        for _ in 0..20 {
+            kobold_step(&mut monster, &mut map, &player, &mut rng);
+            assert_eq!(monster.state, KoboldState::Wander);
+            assert!(map.get_distance(&(monster.x, monster.y), &(6, 1)) <= 1);
+        }
+    }
 }
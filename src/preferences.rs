@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+
+use super::save::user_data_dir;
+use super::{Error, Result};
+
+pub const PREFERENCES_FILENAME: &'static str = "preferences.sqlite3";
+
+const PREFERENCES_TABLE: &'static str = "preferences";
+
+// Change of a volume for one key press, in percent
+const VOLUME_STEP: u8 = 10;
+
+pub fn preferences_path() -> PathBuf {
+    user_data_dir().join(PREFERENCES_FILENAME)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Volume {
+    Master,
+    Music,
+    Effects,
+}
+
+//{{{ Preferences
+/*
+ * Choices of the player which outlive a game, kept per user
+ * next to the save. Volumes are in percent, the master one
+ * scales the other two.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preferences {
+    pub master_volume: u8,
+    pub music_volume: u8,
+    pub effects_volume: u8,
+    pub muted: bool,
+}
+impl Default for Preferences {
+    fn default() -> Preferences {
+        Preferences {
+            master_volume: 100,
+            music_volume: 100,
+            effects_volume: 100,
+            muted: false,
+        }
+    }
+}
+impl Preferences {
+    /*
+     * Defaults when there is no file yet. Unknown names
+     * and values which are not numbers are skipped.
+     */
+    pub fn load(path: &Path) -> Result<Preferences> {
+        let mut preferences = Preferences::default();
+        if !path.is_file() {
+            return Ok(preferences);
+        }
+
+        let conn = Connection::open(path)
+            .map_err(|_| Error::MissingDatabase(path.to_path_buf()))?;
+        let query =
+            String::from("select name, value from ") + PREFERENCES_TABLE + ";";
+        let mut statement = conn.prepare(&query)
+            .map_err(|_| Error::MissingTable(String::from(PREFERENCES_TABLE)))?;
+
+        for maybe_row_content in statement.query_map(&[], |row| {
+            let name: String = row.get(0);
+            let value: Option<i64> = row.get_checked(1).ok();
+            (name, value)
+        })? {
+            let (name, value) = match maybe_row_content? {
+                (name, Some(value)) => (name, value),
+                _ => continue,
+            };
+            // Out of range volumes are brought back into it
+            let percent = value.max(0).min(100) as u8;
+            match name.as_str() {
+                "master_volume" => preferences.master_volume = percent,
+                "music_volume" => preferences.music_volume = percent,
+                "effects_volume" => preferences.effects_volume = percent,
+                "muted" => preferences.muted = value != 0,
+                _ => (),
+            }
+        }
+
+        Ok(preferences)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let cannot_write = || Error::CannotWrite(path.to_path_buf());
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|_| cannot_write())?;
+        }
+
+        let mut conn = Connection::open(path).map_err(|_| cannot_write())?;
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(
+            &(String::from("drop table if exists ") + PREFERENCES_TABLE
+                + "; create table " + PREFERENCES_TABLE
+                + "(name TEXT, value NUMERIC);"),
+        )?;
+        let query = String::from("insert into ") + PREFERENCES_TABLE
+            + "(name, value) values (?, ?);";
+        for &(name, value) in [
+            ("master_volume", self.master_volume as i64),
+            ("music_volume", self.music_volume as i64),
+            ("effects_volume", self.effects_volume as i64),
+            ("muted", self.muted as i64),
+        ].iter()
+        {
+            transaction.execute(&query, &[&name, &value])?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn change_volume(&mut self, volume: Volume, louder: bool) {
+        let value = match volume {
+            Volume::Master => &mut self.master_volume,
+            Volume::Music => &mut self.music_volume,
+            Volume::Effects => &mut self.effects_volume,
+        };
+        *value = if louder {
+            value.saturating_add(VOLUME_STEP).min(100)
+        } else {
+            value.saturating_sub(VOLUME_STEP)
+        };
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    // Percent of the full volume the music is played with
+    pub fn music_level(&self) -> u8 {
+        self.level(self.music_volume)
+    }
+
+    // Percent of the full volume the effects are played with
+    pub fn effects_level(&self) -> u8 {
+        self.level(self.effects_volume)
+    }
+
+    fn level(&self, volume: u8) -> u8 {
+        if self.muted {
+            0
+        } else {
+            (self.master_volume as u16 * volume as u16 / 100) as u8
+        }
+    }
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::testing::temp_dir;
+
+    fn temp_preferences(name: &str) -> PathBuf {
+        temp_dir(name).join(PREFERENCES_FILENAME)
+    }
+
+    #[test]
+    fn preferences_are_kept() {
+        let path = temp_preferences("kept");
+        assert_eq!(Preferences::load(&path).unwrap(), Preferences::default());
+
+        let mut preferences = Preferences::default();
+        preferences.change_volume(Volume::Music, false);
+        preferences.toggle_mute();
+        preferences.save(&path).unwrap();
+        // Saved again over the old file
+        preferences.change_volume(Volume::Effects, false);
+        preferences.save(&path).unwrap();
+
+        let loaded = Preferences::load(&path);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(loaded.unwrap(), preferences);
+    }
+
+    #[test]
+    fn volumes_stay_in_range() {
+        let mut preferences = Preferences::default();
+        preferences.change_volume(Volume::Master, true);
+        assert_eq!(preferences.master_volume, 100);
+
+        preferences.master_volume = 50;
+        preferences.effects_volume = 5;
+        preferences.change_volume(Volume::Effects, false);
+        assert_eq!(preferences.effects_volume, 0);
+        assert_eq!(preferences.music_level(), 50);
+
+        preferences.toggle_mute();
+        assert_eq!(preferences.music_level(), 0);
+    }
+
+    #[test]
+    fn bad_values_are_skipped() {
+        let path = temp_preferences("bad");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "create table preferences(name TEXT, value NUMERIC);
+                insert into preferences(name, value) values
+                    ('master_volume', 300),
+                    ('music_volume', 'loud'),
+                    ('effects_volume', 40),
+                    ('brightness', 2);",
+            )
+            .unwrap();
+
+        let loaded = Preferences::load(&path);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(
+            loaded.unwrap(),
+            Preferences {
+                master_volume: 100,
+                music_volume: 100,
+                effects_volume: 40,
+                muted: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::game::Command;
+use super::{Error, Result};
+
+// First line of every replay file, bump it when the format changes
+const REPLAY_HEADER: &'static str = "kobald replay 1";
+
+//{{{ Replay
+/*
+ * Seed of the map and every command the player gave, in order.
+ * A game started from the seed and fed the same commands
+ * ends up in the same state.
+ *
+ * The file is plain text, one command per line:
+ *
+ *     kobald replay 1
+ *     seed 1528716000
+ *     move 1 0
+ *     refill
+ *     mark 12 7
+ *     help
+ *     close
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub seed: usize,
+    pub commands: Vec<Command>,
+}
+impl Replay {
+    pub fn new(seed: usize) -> Replay {
+        Replay {
+            seed,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, command: &Command) {
+        self.commands.push(command.clone());
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from(REPLAY_HEADER) + "\n";
+        text += &format!("seed {}\n", self.seed);
+        for command in self.commands.iter() {
+            text += &match *command {
+                Command::Move(x_mod, y_mod) => {
+                    format!("move {} {}", x_mod, y_mod)
+                }
+                Command::Refill => String::from("refill"),
+                Command::ToggleMark(x, y) => format!("mark {} {}", x, y),
+                Command::ShowHelp => String::from("help"),
+                Command::CloseScene => String::from("close"),
+            };
+            text += "\n";
+        }
+        text
+    }
+
+    /*
+     * Errors are reported with the line number, starting from 1
+     */
+    pub fn from_text(text: &str) -> ::std::result::Result<Replay, String> {
+        let mut lines = text.lines().enumerate().map(|(n, line)| {
+            (n + 1, line.split_whitespace().collect::<Vec<&str>>())
+        });
+
+        match lines.next() {
+            Some((_, ref words)) if words.join(" ") == REPLAY_HEADER => (),
+            _ => {
+                return Err(format!(
+                    "line 1: should be '{}', older replays are not supported",
+                    REPLAY_HEADER
+                ))
+            }
+        }
+
+        let seed: usize = match lines.next() {
+            Some((_, ref words)) if words.len() == 2 && words[0] == "seed" => {
+                words[1]
+                    .parse()
+                    .map_err(|_| format!("line 2: bad seed {}", words[1]))?
+            }
+            _ => return Err(String::from("line 2: should be 'seed <number>'")),
+        };
+
+        let mut replay = Replay::new(seed);
+        for (line_n, words) in lines {
+            let numbers: Vec<Option<isize>> =
+                words.iter().skip(1).map(|word| word.parse().ok()).collect();
+            let command = match (words.first(), numbers.as_slice()) {
+                (None, _) => continue,
+                (Some(&"move"), &[Some(x_mod), Some(y_mod)]) => {
+                    Command::Move(x_mod, y_mod)
+                }
+                (Some(&"refill"), &[]) => Command::Refill,
+                (Some(&"mark"), &[Some(x), Some(y)]) if x >= 0 && y >= 0 => {
+                    Command::ToggleMark(x as usize, y as usize)
+                }
+                (Some(&"help"), &[]) => Command::ShowHelp,
+                (Some(&"close"), &[]) => Command::CloseScene,
+                _ => {
+                    return Err(format!(
+                        "line {}: unknown command '{}'",
+                        line_n,
+                        words.join(" ")
+                    ))
+                }
+            };
+            replay.commands.push(command);
+        }
+
+        Ok(replay)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_text()).map_err(|e| Error::BadReplay {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Replay> {
+        let bad_replay = |reason: String| Error::BadReplay {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let text =
+            fs::read_to_string(path).map_err(|e| bad_replay(e.to_string()))?;
+        Replay::from_text(&text).map_err(bad_replay)
+    }
+}
+//}}}
+
+//{{{ Playback
+/*
+ * Hands out the commands of a replay one by one, every `interval`,
+ * unless it is paused. While paused, single steps can be asked for.
+ */
+pub struct Playback {
+    commands: Vec<Command>,
+    position: usize,
+    interval: Duration,
+    last_step: Option<Instant>,
+    paused: bool,
+    step_requested: bool,
+}
+impl Playback {
+    pub fn new(replay: Replay, interval: Duration) -> Playback {
+        Playback {
+            commands: replay.commands,
+            position: 0,
+            interval,
+            last_step: None,
+            paused: false,
+            step_requested: false,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Only works while paused
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.commands.len()
+    }
+
+    pub fn next_command(&mut self, now: Instant) -> Option<Command> {
+        let time_to_step = match self.last_step {
+            Some(last_step) => now.duration_since(last_step) >= self.interval,
+            None => true,
+        };
+        let step = if self.paused {
+            self.step_requested
+        } else {
+            time_to_step
+        };
+        self.step_requested = false;
+
+        if !step || self.is_finished() {
+            return None;
+        }
+        self.last_step = Some(now);
+        self.position += 1;
+        Some(self.commands[self.position - 1].clone())
+    }
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::assets::DirectoryAssets;
+    use super::super::game::GameState;
+    use super::super::settings::Settings;
+    use super::super::testing::messages_db;
+
+    #[test]
+    fn replay_text_round_trip() {
+        let mut replay = Replay::new(42);
+        replay.record(&Command::Move(-1, 1));
+        replay.record(&Command::Refill);
+        replay.record(&Command::ToggleMark(12, 7));
+        replay.record(&Command::ShowHelp);
+        replay.record(&Command::CloseScene);
+
+        assert_eq!(Replay::from_text(&replay.to_text()), Ok(replay));
+    }
+
+    #[test]
+    fn bad_replays_are_errors() {
+        for text in [
+            "",
+            "kobald replay 0\nseed 1\n",
+            "kobald replay 1\nmove 1 0\n",
+            "kobald replay 1\nseed 1\nmove 1\n",
+            "kobald replay 1\nseed 1\nmark -1 0\n",
+            "kobald replay 1\nseed 1\njump\n",
+        ].iter()
+        {
+            assert!(Replay::from_text(text).is_err(), "{:?} accepted", text);
+        }
+    }
+
+    #[test]
+    fn playback_pause_and_step() {
+        let mut replay = Replay::new(1);
+        replay.record(&Command::Move(1, 0));
+        replay.record(&Command::Move(0, 1));
+        replay.record(&Command::Refill);
+        let interval = Duration::from_millis(100);
+        let mut playback = Playback::new(replay, interval);
+        let start = Instant::now();
+
+        assert_eq!(playback.next_command(start), Some(Command::Move(1, 0)));
+        assert_eq!(playback.next_command(start), None);
+
+        playback.toggle_pause();
+        assert_eq!(playback.next_command(start + interval), None);
+        playback.step();
+        assert_eq!(
+            playback.next_command(start + interval),
+            Some(Command::Move(0, 1))
+        );
+        assert_eq!(playback.next_command(start + interval * 2), None);
+
+        playback.toggle_pause();
+        assert_eq!(
+            playback.next_command(start + interval * 2),
+            Some(Command::Refill)
+        );
+        assert!(playback.is_finished());
+        assert_eq!(playback.next_command(start + interval * 3), None);
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let db_conn = messages_db(&[("obstacle1", ""), ("danger1", "")]);
+        let assets = DirectoryAssets::new("data");
+        let settings = Settings::default();
+
+        let mut replay = Replay::new(3);
+        for &(x_mod, y_mod) in [(1, 0), (1, 1), (0, 1), (-1, 0)].iter() {
+            replay.record(&Command::Move(x_mod, y_mod));
+        }
+        replay.record(&Command::ToggleMark(5, 5));
+        replay.record(&Command::Refill);
+
+        let play = |replay: &Replay| {
+            let mut game = GameState::init_with_seed(
+                replay.seed,
+                &assets,
+                &db_conn,
+                &settings,
+            ).unwrap();
+            let events: Vec<_> = replay
+                .commands
+                .iter()
+                .flat_map(|command| game.apply(command.clone()))
+                .collect();
+            (events, game.player.x, game.player.y, game.map.marks)
+        };
+
+        assert_eq!(play(&replay), play(&replay));
+    }
+}
@@ -0,0 +1,244 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+use rusqlite::types::{FromSql, ToSql};
+
+use super::game::GameState;
+use super::settings::Settings;
+use super::{Error, Result};
+
+pub const SAVE_FILENAME: &'static str = "save.sqlite3";
+
+// Bump it whenever SAVE_SCHEMA changes, older saves are refused then
+pub const SAVE_VERSION: i64 = 3;
+
+const SAVE_VALUES_TABLE: &'static str = "game";
+
+const SAVE_SCHEMA: &'static str = "
+    create table game(name TEXT, value NUMERIC);
+    create table tiles(
+        x INTEGER,
+        y INTEGER,
+        type TEXT,
+        passable INTEGER,
+        explored INTEGER,
+        curiosity_checked INTEGER,
+        search_text TEXT,
+        icon TEXT
+    );
+    create table marks(x INTEGER, y INTEGER);
+    create table special_locations(name TEXT, x INTEGER, y INTEGER);
+    create table resources(x INTEGER, y INTEGER);
+";
+
+/*
+ * Directory of the per-user files, the save and the preferences.
+ * They are kept apart from the game itself, so it can be installed
+ * somewhere read-only.
+ */
+pub fn user_data_dir() -> PathBuf {
+    let data_dir: Option<PathBuf> = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| {
+            env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+    };
+
+    match data_dir {
+        Some(dir) => dir.join("kobald"),
+        None => PathBuf::from("."),
+    }
+}
+
+pub fn save_path() -> PathBuf {
+    user_data_dir().join(SAVE_FILENAME)
+}
+
+//{{{ write
+pub fn write(game: &GameState, save_path: &Path) -> Result<()> {
+    let cannot_write = |_| Error::CannotWrite(save_path.to_path_buf());
+
+    if let Some(dir) = save_path.parent() {
+        fs::create_dir_all(dir).map_err(&cannot_write)?;
+    }
+
+    // Written aside first, so a failure does not eat the previous save
+    let new_path = save_path.with_extension("new");
+    if new_path.exists() {
+        fs::remove_file(&new_path).map_err(&cannot_write)?;
+    }
+    {
+        let save_conn = Connection::open(&new_path)
+            .map_err(|_| Error::CannotWrite(new_path.clone()))?;
+        save_conn.execute_batch("begin;")?;
+        save_conn.execute_batch(SAVE_SCHEMA)?;
+        save_conn.execute_batch(&format!(
+            "pragma user_version = {};",
+            SAVE_VERSION
+        ))?;
+        game.save(&save_conn)?;
+        save_conn.execute_batch("commit;")?;
+    }
+    fs::rename(&new_path, save_path).map_err(&cannot_write)?;
+
+    Ok(())
+}
+//}}}
+
+//{{{ read
+/*
+ * Messages of the `TextLine` and everything from the settings
+ * are not saved, they are taken from the game database again.
+ */
+pub fn read(
+    save_path: &Path,
+    db_conn: &Connection,
+    settings: &Settings,
+) -> Result<GameState> {
+    if !save_path.is_file() {
+        return Err(Error::MissingDatabase(save_path.to_path_buf()));
+    }
+    let save_conn = Connection::open(save_path)
+        .map_err(|_| Error::MissingDatabase(save_path.to_path_buf()))?;
+
+    let version: i64 =
+        save_conn.query_row("pragma user_version;", &[], |row| row.get(0))?;
+    if version != SAVE_VERSION {
+        return Err(Error::IncompatibleSave {
+            path: save_path.to_path_buf(),
+            version,
+        });
+    }
+
+    GameState::load(&save_conn, db_conn, settings)
+}
+//}}}
+
+pub fn remove(save_path: &Path) -> Result<()> {
+    if save_path.exists() {
+        fs::remove_file(save_path)
+            .map_err(|_| Error::CannotWrite(save_path.to_path_buf()))?;
+    }
+    Ok(())
+}
+
+//{{{ values
+/*
+ * Single values of the game objects are kept in the `game` table
+ * by name, the way `game_settings` keeps settings.
+ */
+pub fn set_value<T: ToSql>(
+    save_conn: &Connection,
+    name: &str,
+    value: T,
+) -> Result<()> {
+    let query = String::from("insert into ") + SAVE_VALUES_TABLE
+        + "(name, value) values (?, ?);";
+    save_conn.execute(&query, &[&name, &value])?;
+    Ok(())
+}
+
+pub fn get_value<T: FromSql>(save_conn: &Connection, name: &str) -> Result<T> {
+    let query = String::from("select value from ") + SAVE_VALUES_TABLE
+        + " where name = ?;";
+    let maybe_value =
+        save_conn.query_row(&query, &[&name], |row| row.get_checked(0));
+
+    match maybe_value {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(_)) => Err(Error::corrupt_blob(SAVE_VALUES_TABLE, name)),
+        Err(_) => Err(Error::missing_row(SAVE_VALUES_TABLE, name)),
+    }
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::assets::DirectoryAssets;
+    use super::super::game::Command;
+    use super::super::testing::{messages_db, temp_dir};
+
+    fn game_db() -> Connection {
+        messages_db(&[
+            ("start", ""),
+            ("obstacle1", ""),
+            ("resource_found1", ""),
+        ])
+    }
+
+    fn temp_save(name: &str) -> PathBuf {
+        temp_dir(name).join(SAVE_FILENAME)
+    }
+
+    #[test]
+    fn saved_game_is_resumed() {
+        let db_conn = game_db();
+        let settings = Settings::default();
+        let mut game =
+            GameState::init_with_seed(
+                7,
+                &DirectoryAssets::new("data"),
+                &db_conn,
+                &settings,
+            ).unwrap();
+        for &(x_mod, y_mod) in [(1, 0), (0, 1), (-1, 0), (0, -1)].iter() {
+            game.apply(Command::Move(x_mod, y_mod));
+        }
+        game.apply(Command::ToggleMark(3, 4));
+
+        let save_path = temp_save("resume");
+        write(&game, &save_path).unwrap();
+        let mut resumed = read(&save_path, &db_conn, &settings).unwrap();
+        remove(&save_path).unwrap();
+
+        assert_eq!(resumed.seed(), 7);
+        assert_eq!(resumed.map.marks, game.map.marks);
+        assert_eq!(
+            (resumed.player.x, resumed.player.y),
+            (game.player.x, game.player.y)
+        );
+        assert_eq!(
+            resumed.player.get_resource_state(),
+            game.player.get_resource_state()
+        );
+        assert_eq!(
+            (resumed.monster.x, resumed.monster.y),
+            (game.monster.x, game.monster.y)
+        );
+
+        // Both continue the same way
+        for &(x_mod, y_mod) in [(1, 0), (1, 1), (0, 1)].iter() {
+            assert_eq!(
+                resumed.apply(Command::Move(x_mod, y_mod)),
+                game.apply(Command::Move(x_mod, y_mod))
+            );
+        }
+        assert_eq!(
+            (resumed.monster.x, resumed.monster.y),
+            (game.monster.x, game.monster.y)
+        );
+    }
+
+    #[test]
+    fn old_save_is_refused() {
+        let save_path = temp_save("old");
+        fs::create_dir_all(save_path.parent().unwrap()).unwrap();
+        {
+            let save_conn = Connection::open(&save_path).unwrap();
+            save_conn.execute_batch(SAVE_SCHEMA).unwrap();
+        }
+
+        let result = read(&save_path, &game_db(), &Settings::default());
+        remove(&save_path).unwrap();
+        match result {
+            Err(Error::IncompatibleSave { version: 0, .. }) => (),
+            _ => panic!("old save accepted"),
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+use super::{Error, Result};
+
+const DB_SETTINGS_TABLE: &'static str = "game_settings";
+
+// GUI elements which areas are described in the settings table
+const GUI_ELEMENTS: [&'static str; 6] =
+    ["bg", "map", "text", "flask", "scene", "help"];
+
+//{{{ Area
+/*
+ * Place of a GUI element inside the window
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Area {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+impl Default for Area {
+    fn default() -> Area {
+        Area { x: 0, y: 0, w: 1, h: 1 }
+    }
+}
+//}}}
+
+//{{{ Settings
+/*
+ * Everything from the `game_settings` table, read once.
+ * Comments show the default used when a setting is absent.
+ */
+#[derive(Clone, Debug)]
+pub struct Settings {
+    // Window title, "Debug"
+    pub game_name: String,
+
+    // `<element>_x`, `<element>_y`, `<element>_w`, `<element>_h`,
+    // 0, 0, 1, 1
+    areas: HashMap<String, Area>,
+
+    // Text wrapping width of the scenes, 100
+    pub textscene_max_width: u32,
+    // Margin of the text inside the scene, 10
+    pub textscene_margin: i32,
+    // Text wrapping width of the thoughts line, 100
+    pub textline_max_width: u32,
+    // Font size of the thoughts and scenes, 12
+    pub textline_font_size: u16,
+    // Font size of the endings, 18
+    pub endings_font_size: u16,
+    // Turns for the thought to stay visible, 3
+    pub textline_time_max: u8,
+
+    // Lantern radius, 5
+    pub visible_distance: u8,
+    // Lantern radius when the kobold is near, 2
+    pub visible_distance_danger: u8,
+    // Turns one oil refill lasts, 10, at least 1
+    pub resource_max: u8,
+    // Oil refills at the start, 3
+    pub resource_start: u8,
+    // Side of the map section with one oil spot, `resource_max`, at least 1
+    pub resource_distance: u32,
+
+    // Upper bound of obstacles on the map, 6, at least 2
+    pub obstacle_max: u8,
+    // Map generation tries before giving up, 100, at least 1
+    pub map_max_tries: u8,
+    // Cards across the map, 3, at least 1
+    pub field_width: u8,
+    // Cards down the map, 3, at least 1
+    pub field_height: u8,
+
+    // Distance at which the kobold is felt, 5
+    pub kobold_danger_dist: u8,
+    // Distance at which the kobold hears the player, 2
+    pub kobold_hear_dist: u8,
+    // How far past the lantern radius the kobold notices its light, 3
+    pub kobold_light_dist: u8,
+    // Turns the kobold hunts without noticing the player, 6
+    pub kobold_track_turns: u8,
+    // How far the kobold wanders from where it started, 4
+    pub kobold_wander_dist: u8,
+
+    // Names from the `musics` table for every state of the game,
+    // "" for the first track
+    pub music_exploring: String,
+    pub music_danger: String,
+    pub music_lamp_out: String,
+    pub music_ending: String,
+    // Milliseconds one track fades out and the next one fades in, 1000
+    pub music_fade_ms: u16,
+
+    // Percent a lower and a higher copy of every sound effect are
+    // pitched by, so one of three pitches is played at random, 0
+    pub sound_pitch_jitter: u8,
+    // Percent a sound effect is made quieter by at random, up to, 0
+    pub sound_volume_jitter: u8,
+}
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            game_name: String::from("Debug"),
+            areas: HashMap::new(),
+            textscene_max_width: 100,
+            textscene_margin: 10,
+            textline_max_width: 100,
+            textline_font_size: 12,
+            endings_font_size: 18,
+            textline_time_max: 3,
+            visible_distance: 5,
+            visible_distance_danger: 2,
+            resource_max: 10,
+            resource_start: 3,
+            resource_distance: 10,
+            obstacle_max: 6,
+            map_max_tries: 100,
+            field_width: 3,
+            field_height: 3,
+            kobold_danger_dist: 5,
+            kobold_hear_dist: 2,
+            kobold_light_dist: 3,
+            kobold_track_turns: 6,
+            kobold_wander_dist: 4,
+            music_exploring: String::new(),
+            music_danger: String::new(),
+            music_lamp_out: String::new(),
+            music_ending: String::new(),
+            music_fade_ms: 1000,
+            sound_pitch_jitter: 0,
+            sound_volume_jitter: 0,
+        }
+    }
+}
+impl Settings {
+    pub fn load(db_conn: &Connection) -> Result<Settings> {
+        let query = String::from("select setting, value from ")
+            + DB_SETTINGS_TABLE + ";";
+        let mut statement = db_conn
+            .prepare(&query)
+            .map_err(|_| Error::MissingTable(String::from(DB_SETTINGS_TABLE)))?;
+
+        let mut raw = RawSettings {
+            values: HashMap::new(),
+        };
+        for maybe_row_content in statement.query_map(&[], |row| {
+            let setting: String = row.get(0);
+            let value: Value = row.get(1);
+            (setting, value)
+        })? {
+            let (setting, value) = maybe_row_content?;
+            raw.values.insert(setting.to_lowercase(), value);
+        }
+
+        raw.parse()
+    }
+
+    pub fn area(&self, name: &str) -> Area {
+        match self.areas.get(name) {
+            Some(area) => *area,
+            None => Area::default(),
+        }
+    }
+}
+//}}}
+
+//{{{ RawSettings
+struct RawSettings {
+    values: HashMap<String, Value>,
+}
+impl RawSettings {
+    fn parse(&self) -> Result<Settings> {
+        let default = Settings::default();
+
+        let u8_max = u8::max_value() as i64;
+        let u16_max = u16::max_value() as i64;
+        let u32_max = u32::max_value() as i64;
+        let i32_min = i32::min_value() as i64;
+        let i32_max = i32::max_value() as i64;
+
+        let mut areas: HashMap<String, Area> = HashMap::new();
+        for element in GUI_ELEMENTS.iter() {
+            let name = |side: &str| String::from(*element) + "_" + side;
+            let area = Area {
+                x: self.integer(&name("x"), 0, i32_min, i32_max)? as i32,
+                y: self.integer(&name("y"), 0, i32_min, i32_max)? as i32,
+                w: self.integer(&name("w"), 1, 1, u32_max)? as u32,
+                h: self.integer(&name("h"), 1, 1, u32_max)? as u32,
+            };
+            areas.insert(String::from(*element), area);
+        }
+
+        let resource_max = self.integer(
+            "resource_max",
+            default.resource_max as i64,
+            1,
+            u8_max,
+        )? as u8;
+
+        Ok(Settings {
+            game_name: self.text("game_name", &default.game_name)?,
+            areas,
+            textscene_max_width: self.integer(
+                "textscene_max_width",
+                default.textscene_max_width as i64,
+                1,
+                u32_max,
+            )? as u32,
+            textscene_margin: self.integer(
+                "textscene_margin",
+                default.textscene_margin as i64,
+                0,
+                i32_max,
+            )? as i32,
+            textline_max_width: self.integer(
+                "textline_max_width",
+                default.textline_max_width as i64,
+                1,
+                u32_max,
+            )? as u32,
+            textline_font_size: self.integer(
+                "textline_font_size",
+                default.textline_font_size as i64,
+                1,
+                u16_max,
+            )? as u16,
+            endings_font_size: self.integer(
+                "endings_font_size",
+                default.endings_font_size as i64,
+                1,
+                u16_max,
+            )? as u16,
+            textline_time_max: self.integer(
+                "textline_time_max",
+                default.textline_time_max as i64,
+                0,
+                u8_max,
+            )? as u8,
+            visible_distance: self.integer(
+                "visible_distance",
+                default.visible_distance as i64,
+                0,
+                u8_max,
+            )? as u8,
+            visible_distance_danger: self.integer(
+                "visible_distance_danger",
+                default.visible_distance_danger as i64,
+                0,
+                u8_max,
+            )? as u8,
+            resource_max,
+            resource_start: self.integer(
+                "resource_start",
+                default.resource_start as i64,
+                0,
+                u8_max,
+            )? as u8,
+            resource_distance: self.integer(
+                "resource_distance",
+                resource_max as i64,
+                1,
+                u32_max,
+            )? as u32,
+            // gen_range(1, obstacle_max) needs at least 2
+            obstacle_max: self.integer(
+                "obstacle_max",
+                default.obstacle_max as i64,
+                2,
+                u8_max,
+            )? as u8,
+            map_max_tries: self.integer(
+                "map_max_tries",
+                default.map_max_tries as i64,
+                1,
+                u8_max,
+            )? as u8,
+            field_width: self.integer(
+                "field_width",
+                default.field_width as i64,
+                1,
+                u8_max,
+            )? as u8,
+            field_height: self.integer(
+                "field_height",
+                default.field_height as i64,
+                1,
+                u8_max,
+            )? as u8,
+            // One more is added to it, so it should fit
+            kobold_danger_dist: self.integer(
+                "kobold_danger_dist",
+                default.kobold_danger_dist as i64,
+                0,
+                u8_max - 1,
+            )? as u8,
+            kobold_hear_dist: self.integer(
+                "kobold_hear_dist",
+                default.kobold_hear_dist as i64,
+                0,
+                u8_max,
+            )? as u8,
+            kobold_light_dist: self.integer(
+                "kobold_light_dist",
+                default.kobold_light_dist as i64,
+                0,
+                u8_max,
+            )? as u8,
+            kobold_track_turns: self.integer(
+                "kobold_track_turns",
+                default.kobold_track_turns as i64,
+                0,
+                u8_max,
+            )? as u8,
+            kobold_wander_dist: self.integer(
+                "kobold_wander_dist",
+                default.kobold_wander_dist as i64,
+                1,
+                u8_max,
+            )? as u8,
+            music_exploring: self.text(
+                "music_exploring",
+                &default.music_exploring,
+            )?,
+            music_danger: self.text("music_danger", &default.music_danger)?,
+            music_lamp_out: self.text(
+                "music_lamp_out",
+                &default.music_lamp_out,
+            )?,
+            music_ending: self.text("music_ending", &default.music_ending)?,
+            music_fade_ms: self.integer(
+                "music_fade_ms",
+                default.music_fade_ms as i64,
+                0,
+                u16_max,
+            )? as u16,
+            sound_pitch_jitter: self.integer(
+                "sound_pitch_jitter",
+                default.sound_pitch_jitter as i64,
+                0,
+                50,
+            )? as u8,
+            sound_volume_jitter: self.integer(
+                "sound_volume_jitter",
+                default.sound_volume_jitter as i64,
+                0,
+                100,
+            )? as u8,
+        })
+    }
+
+    fn integer(
+        &self,
+        setting: &str,
+        default: i64,
+        min: i64,
+        max: i64,
+    ) -> Result<i64> {
+        match self.values.get(setting) {
+            None => Ok(default),
+            Some(&Value::Integer(value)) => if value < min || value > max {
+                Err(Error::InvalidSetting {
+                    setting: String::from(setting),
+                    reason: format!("{} is not in {}..{}", value, min, max),
+                })
+            } else {
+                Ok(value)
+            },
+            Some(_) => Err(Error::InvalidSetting {
+                setting: String::from(setting),
+                reason: String::from("should be an integer"),
+            }),
+        }
+    }
+
+    fn text(&self, setting: &str, default: &str) -> Result<String> {
+        match self.values.get(setting) {
+            None => Ok(String::from(default)),
+            Some(&Value::Text(ref value)) => Ok(value.clone()),
+            Some(_) => Err(Error::InvalidSetting {
+                setting: String::from(setting),
+                reason: String::from("should be a text"),
+            }),
+        }
+    }
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::testing::memory_db;
+
+    fn settings_db(rows: &str) -> Connection {
+        memory_db(
+            &(String::from(
+                "create table game_settings(setting TEXT, value NUMERIC);",
+            ) + rows),
+        )
+    }
+
+    #[test]
+    fn absent_settings_are_defaults() {
+        let settings = Settings::load(&settings_db("")).unwrap();
+        assert_eq!(settings.resource_max, 10);
+        assert_eq!(settings.resource_distance, 10);
+        assert_eq!(settings.obstacle_max, 6);
+        assert_eq!(settings.area("map"), Area::default());
+    }
+
+    #[test]
+    fn settings_are_read() {
+        let settings = Settings::load(&settings_db(
+            "insert into game_settings(setting, value) values
+                ('game_name', 'Kobold'),
+                ('map_x', 24),
+                ('map_w', 576),
+                ('resource_max', 7),
+                ('music_danger', 'chase.ogg');",
+        )).unwrap();
+        assert_eq!(settings.game_name, "Kobold");
+        assert_eq!(settings.music_danger, "chase.ogg");
+        assert_eq!(settings.music_exploring, "");
+        assert_eq!(settings.resource_max, 7);
+        assert_eq!(settings.resource_distance, 7);
+        assert_eq!(
+            settings.area("map"),
+            Area {
+                x: 24,
+                y: 0,
+                w: 576,
+                h: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn out_of_range_settings_are_errors() {
+        for row in [
+            "('resource_max', 0)",
+            "('obstacle_max', 1)",
+            "('visible_distance', 300)",
+            "('map_max_tries', 'many')",
+            "('field_width', 0)",
+            "('kobold_wander_dist', 0)",
+            "('sound_pitch_jitter', 60)",
+        ].iter()
+        {
+            let rows = String::from(
+                "insert into game_settings(setting, value) values ",
+            ) + row + ";";
+            match Settings::load(&settings_db(&rows)) {
+                Err(Error::InvalidSetting { .. }) => (),
+                _ => panic!("{} accepted", row),
+            }
+        }
+    }
+}
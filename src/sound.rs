@@ -1,65 +1,726 @@
+use std::cmp;
 use std::collections::HashMap;
-use std::path::PathBuf;
 
+use rand;
+use rand::Rng;
 use sdl2;
+use sdl2::{AudioSubsystem, Sdl};
 use sdl2::rwops::RWops;
-use sdl2::mixer::LoaderRWops;
-use sdl2::mixer::Chunk;
-use rusqlite::{Connection, DatabaseName, OpenFlags};
-use std::io::Read; // For Blob
-
-use super::DB_FILENAME;
-
-pub fn load_sounds() -> HashMap<String, Chunk> {
-    // Setting up database connection
-    let db_path: PathBuf = [".", DB_FILENAME].iter().collect();
-    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-    let db_connection =
-        Connection::open_with_flags(&db_path, flags).expect("Cannot read data");
-
-    // Getting effects
-    let query = "select name, rowid from sound_effects;";
-    let mut statement =
-        db_connection.prepare(&query).expect("Cannot prepare query");
-
-    let mut effects: HashMap<String, Chunk> = HashMap::new();
-    for maybe_row_content in statement
-        .query_map(&[], |row| {
-            let name: String = row.get(0);
-            let id: i64 = row.get(1);
-            (id, name)
+use sdl2::mixer::{Channel, Chunk, Group, LoaderRWops, Music};
+use sdl2::mixer::{Sdl2MixerContext, MAX_VOLUME};
+use sdl2::mixer::{DEFAULT_CHANNELS, DEFAULT_FORMAT, INIT_MP3};
+
+use super::assets::{AssetKind, AssetSource};
+use super::map::Map;
+use super::preferences::Preferences;
+use super::settings::Settings;
+use super::{Error, Result};
+
+// Enough for the kobold, the lamp and the shout at once
+const EFFECT_CHANNELS: i32 = 8;
+
+// Tiles away from the player at which a sound dies out
+const HEARING_RANGE: f32 = 16.0;
+
+// Extra mixer distance, 0 to 255, for every wall or obstacle
+// between the sound and the player
+const OCCLUSION_PENALTY: u32 = 40;
+
+// Wav formats which can be pitched: PCM, IEEE float and extensible
+const WAV_FORMATS: [u16; 3] = [1, 3, 0xFFFE];
+
+//{{{ AudioBackend
+/*
+ * What the game asks of the audio. Sounds are played for events,
+ * see `event_key`; events without sounds are silent.
+ */
+pub trait AudioBackend {
+    // A random variant of the event sound, placed on the map if
+    // there is a position
+    fn play_event_sound_at(
+        &self,
+        event: &str,
+        position: Option<SoundPosition>,
+    );
+
+    fn play_event_sound(&self, event: &str) {
+        self.play_event_sound_at(event, None);
+    }
+
+    // Should be called every frame
+    fn set_music_state(&mut self, state: MusicState);
+
+    fn apply_volumes(&mut self, preferences: &Preferences);
+}
+
+/*
+ * SDL2 audio when it can be opened, silence otherwise.
+ * Nothing is opened when `enabled` is false.
+ */
+pub fn open_audio(
+    sdl_context: &Sdl,
+    assets: &AssetSource,
+    settings: &Settings,
+    enabled: bool,
+) -> Box<AudioBackend> {
+    if !enabled {
+        return Box::new(NullAudio);
+    }
+
+    match SdlAudio::open(sdl_context, assets, settings) {
+        Ok(audio) => Box::new(audio),
+        Err(error) => {
+            eprintln!("Cannot open audio, playing without it. {}", error);
+            Box::new(NullAudio)
+        }
+    }
+}
+//}}}
+
+//{{{ NullAudio
+/*
+ * Plays nothing, for sessions without an audio device
+ */
+pub struct NullAudio;
+impl AudioBackend for NullAudio {
+    fn play_event_sound_at(
+        &self,
+        _event: &str,
+        _position: Option<SoundPosition>,
+    ) {
+    }
+
+    fn set_music_state(&mut self, _state: MusicState) {}
+
+    fn apply_volumes(&mut self, _preferences: &Preferences) {}
+}
+//}}}
+
+//{{{ SdlAudio
+/*
+ * SDL2 audio and mixer with the loaded sounds. Fields are dropped
+ * in order, so the sounds go before the mixer is closed.
+ */
+pub struct SdlAudio {
+    // Variants of every event, pitched copies included
+    effects: HashMap<String, Vec<Chunk>>,
+    volume_jitter: u8,
+    // None when the music cannot be loaded, the effects play anyway
+    music: Option<MusicManager>,
+    _opened_device: OpenedDevice,
+    _sdl_mixer: Sdl2MixerContext,
+    _sdl_audio: AudioSubsystem,
+}
+impl SdlAudio {
+    pub fn open(
+        sdl_context: &Sdl,
+        assets: &AssetSource,
+        settings: &Settings,
+    ) -> Result<SdlAudio> {
+        let sdl_audio =
+            sdl_context.audio().map_err(|e| Error::sdl("audio", e))?;
+
+        let chunk_size = 1_024;
+        let frequency = 44_100;
+        sdl2::mixer::open_audio(
+            frequency,
+            DEFAULT_FORMAT,
+            DEFAULT_CHANNELS,
+            chunk_size,
+        ).map_err(|e| Error::sdl("mixer", e))?;
+        // From here on the device is closed on every early return
+        let opened_device = OpenedDevice;
+
+        let sdl_mixer =
+            sdl2::mixer::init(INIT_MP3).map_err(|e| Error::sdl("mixer", e))?;
+
+        sdl2::mixer::allocate_channels(EFFECT_CHANNELS);
+
+        let music = match MusicManager::new(assets, settings) {
+            Ok(music) => Some(music),
+            Err(error) => {
+                eprintln!(
+                    "Cannot load the music, playing without it. {}",
+                    error
+                );
+                None
+            }
+        };
+
+        Ok(SdlAudio {
+            effects: load_sounds(assets, settings.sound_pitch_jitter)?,
+            volume_jitter: settings.sound_volume_jitter,
+            music,
+            _opened_device: opened_device,
+            _sdl_mixer: sdl_mixer,
+            _sdl_audio: sdl_audio,
         })
-        .unwrap()
-    {
-        if let Ok((id, name)) = maybe_row_content {
-            let mut blob = db_connection
-                .blob_open(
-                    DatabaseName::Main,
-                    "sound_effects",
-                    "effect",
-                    id,
-                    true, // Read-Only
-                )
-                .expect("Cannot read image blob.");
-
-            let mut bytes: Vec<u8> = Vec::new();
-            blob.read_to_end(&mut bytes)
-                .expect("Cannot read image bytes.");
-            let stream = RWops::from_bytes(&bytes)
-                .expect("Cannot open image bytes as a stream.");
-            let effect: Chunk =
-                stream.load_wav().expect("Cannot load sound effect");
-
-            effects.insert(name, effect);
-        }
-    }
-
-    effects
-}
-
-pub fn play_effect(effect: &Chunk) {
-    let channels = sdl2::mixer::Channel::all();
-    if let Err(_) = channels.play(effect, 0) {
-        println!("Cannot play sound effect");
+    }
+}
+impl AudioBackend for SdlAudio {
+    fn play_event_sound_at(
+        &self,
+        event: &str,
+        position: Option<SoundPosition>,
+    ) {
+        let variants = match self.effects.get(event) {
+            Some(variants) => variants,
+            None => return,
+        };
+
+        // Sounds are not a part of the game, so the game RNG
+        // is left alone and replays stay the same
+        let mut rng = rand::thread_rng();
+        if let Some(effect) = rng.choose(variants) {
+            let quieter = rng.gen_range(0, self.volume_jitter as i32 + 1);
+            play(effect, position, MAX_VOLUME * (100 - quieter) / 100);
+        }
+    }
+
+    fn set_music_state(&mut self, state: MusicState) {
+        if let Some(ref mut music) = self.music {
+            music.set_state(state);
+            music.update();
+        }
+    }
+
+    fn apply_volumes(&mut self, preferences: &Preferences) {
+        Music::set_volume(mixer_volume(preferences.music_level()));
+        for variants in self.effects.values_mut() {
+            for effect in variants.iter_mut() {
+                effect.set_volume(mixer_volume(preferences.effects_level()));
+            }
+        }
+    }
+}
+
+// Closes the device of `sdl2::mixer::open_audio` when dropped
+struct OpenedDevice;
+impl Drop for OpenedDevice {
+    fn drop(&mut self) {
+        sdl2::mixer::close_audio();
+    }
+}
+
+/*
+ * Every wav also gets a lower and a higher copy,
+ * `pitch_jitter` percent away, unless it is 0
+ */
+fn load_sounds(
+    assets: &AssetSource,
+    pitch_jitter: u8,
+) -> Result<HashMap<String, Vec<Chunk>>> {
+    let mut effects: HashMap<String, Vec<Chunk>> = HashMap::new();
+    for asset in assets.load(AssetKind::SoundEffect)? {
+        let mut variants: Vec<Chunk> =
+            vec![load_chunk(&asset.bytes, &asset.name)?];
+        if pitch_jitter > 0 {
+            let jitter = pitch_jitter as f32 / 100.0;
+            for factor in [1.0 - jitter, 1.0 + jitter].iter() {
+                // Compressed wavs are played as they are
+                if let Some(bytes) = pitched_wav(&asset.bytes, *factor) {
+                    variants.push(load_chunk(&bytes, &asset.name)?);
+                }
+            }
+        }
+
+        effects
+            .entry(String::from(event_key(&asset.name)))
+            .or_insert_with(Vec::new)
+            .extend(variants);
+    }
+
+    Ok(effects)
+}
+
+fn load_chunk(bytes: &[u8], name: &str) -> Result<Chunk> {
+    let effects_table = AssetKind::SoundEffect.table();
+    let stream = RWops::from_bytes(bytes)
+        .map_err(|_| Error::corrupt_blob(effects_table, name))?;
+    stream
+        .load_wav()
+        .map_err(|_| Error::corrupt_blob(effects_table, name))
+}
+
+fn mixer_volume(level: u8) -> i32 {
+    level as i32 * MAX_VOLUME / 100
+}
+//}}}
+
+//{{{ Variants
+/*
+ * Event of a sound effect: its name without the extension and
+ * the variant number, the way messages are numbered. Both
+ * `resource_gone1.wav` and `resource_gone2.wav` are played
+ * for "resource_gone".
+ */
+pub fn event_key(name: &str) -> &str {
+    let stem = match name.rfind('.') {
+        Some(dot) => &name[..dot],
+        None => name,
+    };
+    stem.trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+/*
+ * The wav played faster (factor over 1) or slower, which raises
+ * or lowers its pitch. None for compressed or broken wavs.
+ */
+fn pitched_wav(wav: &[u8], factor: f32) -> Option<Vec<u8>> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut fmt: Option<&[u8]> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut at = 12;
+    while at + 8 <= wav.len() {
+        let size = read_u32(&wav[at + 4..at + 8]) as usize;
+        let end = (at + 8).saturating_add(size).min(wav.len());
+        match &wav[at..at + 4] {
+            b"fmt " => fmt = Some(&wav[at + 8..end]),
+            b"data" => data = Some(&wav[at + 8..end]),
+            _ => (),
+        }
+        // Chunks are padded to an even size
+        at = end + size % 2;
+    }
+    let (fmt, data) = match (fmt, data) {
+        (Some(fmt), Some(data)) if fmt.len() >= 16 => (fmt, data),
+        _ => return None,
+    };
+    let format = fmt[0] as u16 | (fmt[1] as u16) << 8;
+    let frame_size = (fmt[12] as usize) | (fmt[13] as usize) << 8;
+    if !WAV_FORMATS.contains(&format) || frame_size == 0 || factor <= 0.0 {
+        return None;
+    }
+
+    // Frames are taken whole, the nearest one for every new frame
+    let frames = data.len() / frame_size;
+    let pitched_frames = (frames as f32 / factor) as usize;
+    let mut samples: Vec<u8> =
+        Vec::with_capacity(pitched_frames * frame_size);
+    for frame in 0..pitched_frames {
+        let source = ((frame as f32 * factor) as usize).min(frames - 1);
+        let source = source * frame_size;
+        samples.extend_from_slice(&data[source..source + frame_size]);
+    }
+
+    let mut pitched: Vec<u8> = Vec::new();
+    let riff_size = 4 + 8 + fmt.len() + fmt.len() % 2 + 8 + samples.len()
+        + samples.len() % 2;
+    pitched.extend_from_slice(b"RIFF");
+    push_u32(&mut pitched, riff_size);
+    pitched.extend_from_slice(b"WAVE");
+    for &(id, body) in [(b"fmt ", fmt), (b"data", &samples[..])].iter() {
+        pitched.extend_from_slice(id);
+        push_u32(&mut pitched, body.len());
+        pitched.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            pitched.push(0);
+        }
+    }
+
+    Some(pitched)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: usize) {
+    for shift in [0, 8, 16, 24].iter() {
+        bytes.push((value >> shift) as u8);
+    }
+}
+//}}}
+
+//{{{ Music
+/*
+ * What the music follows, see `GameState::music_state`
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MusicState {
+    Exploring,
+    Danger,
+    LampOut,
+    Ending,
+}
+
+/*
+ * Plays the track of the game state. SDL_mixer has one music
+ * stream, so the old track fades out before the new one fades in.
+ */
+pub struct MusicManager {
+    tracks: HashMap<String, Music<'static>>,
+    choices: HashMap<MusicState, String>,
+    fade_ms: i32,
+    // Playing, or fading out when `next` is set
+    current: Option<String>,
+    next: Option<String>,
+}
+impl MusicManager {
+    /*
+     * Every track stays in memory until the game is closed
+     */
+    pub fn new(
+        assets: &AssetSource,
+        settings: &Settings,
+    ) -> Result<MusicManager> {
+        let musics_table = AssetKind::Music.table();
+
+        let mut tracks: HashMap<String, Music<'static>> = HashMap::new();
+        let mut first_track: Option<String> = None;
+        for asset in assets.load(AssetKind::Music)? {
+            let name = asset.name;
+            // Music is streamed from the bytes while it plays
+            let bytes: &'static [u8] =
+                Box::leak(asset.bytes.into_boxed_slice());
+            let music = Music::from_static_bytes(bytes)
+                .map_err(|_| Error::corrupt_blob(musics_table, &name))?;
+
+            first_track = first_track.or_else(|| Some(name.clone()));
+            tracks.insert(name, music);
+        }
+        let first_track = match first_track {
+            Some(name) => name,
+            None => return Err(Error::missing_row(musics_table, 1)),
+        };
+
+        let mut choices: HashMap<MusicState, String> = HashMap::new();
+        for &(state, name) in [
+            (MusicState::Exploring, &settings.music_exploring),
+            (MusicState::Danger, &settings.music_danger),
+            (MusicState::LampOut, &settings.music_lamp_out),
+            (MusicState::Ending, &settings.music_ending),
+        ].iter()
+        {
+            let name = if name.is_empty() {
+                first_track.clone()
+            } else if tracks.contains_key(name) {
+                name.clone()
+            } else {
+                return Err(Error::missing_row(musics_table, name));
+            };
+            choices.insert(state, name);
+        }
+
+        Ok(MusicManager {
+            tracks,
+            choices,
+            fade_ms: settings.music_fade_ms as i32,
+            current: None,
+            next: None,
+        })
+    }
+
+    pub fn set_state(&mut self, state: MusicState) {
+        let track = self.choices[&state].clone();
+        let coming = match self.next {
+            Some(ref next) => Some(next),
+            None => self.current.as_ref(),
+        };
+        if coming == Some(&track) {
+            return;
+        }
+
+        if self.current.is_some() && self.next.is_none() {
+            if let Err(error) = Music::fade_out(self.fade_ms) {
+                eprintln!("Cannot fade the music out. {}", error);
+            }
+        }
+        self.next = Some(track);
+        self.update();
+    }
+
+    /*
+     * Starts the next track once the previous one has faded out,
+     * should be called every frame
+     */
+    pub fn update(&mut self) {
+        if self.next.is_none() || Music::is_playing() {
+            return;
+        }
+
+        if let Some(track) = self.next.take() {
+            if let Err(error) = self.tracks[&track].fade_in(-1, self.fade_ms) {
+                eprintln!("Cannot play {}. {}", track, error);
+            }
+            self.current = Some(track);
+        }
+    }
+}
+//}}}
+
+//{{{ Positional sounds
+/*
+ * Where a sound is heard from, the way SDL_mixer takes it:
+ * the angle goes clockwise from straight up the map, in degrees,
+ * the distance goes from 0 (right here) to 255 (barely heard).
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SoundPosition {
+    pub angle: i16,
+    pub distance: u8,
+}
+
+/*
+ * None when the sound is too far or too muffled to be heard
+ */
+pub fn sound_position(
+    map: &Map,
+    listener: (usize, usize),
+    source: (usize, usize),
+) -> Option<SoundPosition> {
+    let (angle, distance) = angle_and_distance(map, listener, source);
+
+    if distance > 255 {
+        None
+    } else {
+        Some(SoundPosition {
+            angle,
+            distance: distance as u8,
+        })
+    }
+}
+
+/*
+ * Loud sounds are heard from anywhere on the map,
+ * barely when they are out of hearing range
+ */
+pub fn loud_sound_position(
+    map: &Map,
+    listener: (usize, usize),
+    source: (usize, usize),
+) -> SoundPosition {
+    let (angle, distance) = angle_and_distance(map, listener, source);
+
+    SoundPosition {
+        angle,
+        distance: cmp::min(distance, 255) as u8,
+    }
+}
+
+fn angle_and_distance(
+    map: &Map,
+    listener: (usize, usize),
+    source: (usize, usize),
+) -> (i16, u32) {
+    let dx = source.0 as f32 - listener.0 as f32;
+    let dy = source.1 as f32 - listener.1 as f32;
+
+    // Up the map is ahead, so y goes the other way
+    let angle = dx.atan2(-dy).to_degrees().round() as i16;
+    let angle = (angle + 360) % 360;
+
+    let walls = line_between(listener, source)
+        .iter()
+        .filter(|location| map.is_opaque(location))
+        .count() as u32;
+    let distance = ((dx * dx + dy * dy).sqrt() / HEARING_RANGE * 255.0)
+        .round() as u32
+        + walls * OCCLUSION_PENALTY;
+
+    (angle, distance)
+}
+
+/*
+ * Tiles of a straight line, without both ends
+ */
+fn line_between(
+    start: (usize, usize),
+    end: (usize, usize),
+) -> Vec<(usize, usize)> {
+    let (mut x, mut y) = (start.0 as isize, start.1 as isize);
+    let (end_x, end_y) = (end.0 as isize, end.1 as isize);
+    let dx = (end_x - x).abs();
+    let dy = -(end_y - y).abs();
+    let step_x = if x < end_x { 1 } else { -1 };
+    let step_y = if y < end_y { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    let mut line: Vec<(usize, usize)> = Vec::new();
+    loop {
+        if (x, y) == (end_x, end_y) {
+            break;
+        }
+        if (x, y) != (start.0 as isize, start.1 as isize) {
+            line.push((x as usize, y as usize));
+        }
+
+        let double_error = 2 * error;
+        if double_error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if double_error <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+
+    line
+}
+//}}}
+
+//{{{ Playing
+fn play(effect: &Chunk, position: Option<SoundPosition>, volume: i32) {
+    // Every sound takes a channel of its own
+    let channel: Channel = match Group(-1).find_available() {
+        Some(channel) => channel,
+        None => {
+            eprintln!("Cannot play sound effect, all channels are busy");
+            return;
+        }
+    };
+
+    let placed = match position {
+        Some(position) => {
+            channel.set_position(position.angle, position.distance)
+        }
+        // The previous sound of the channel could have been placed
+        None => channel.unset_position(),
+    };
+    channel.set_volume(volume);
+    if placed.is_err() || channel.play(effect, 0).is_err() {
+        eprintln!("Cannot play sound effect");
+    }
+}
+//}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(rows: &[&str]) -> Map {
+        Map::from_ascii(&rows.join("\n")).unwrap().0
+    }
+
+    #[test]
+    fn sounds_are_panned_around_the_player() {
+        let map = map(&["_____", "_____", "_____", "_____", "_____"]);
+        let angle = |source| {
+            sound_position(&map, (2, 2), source).unwrap().angle
+        };
+
+        assert_eq!(angle((2, 0)), 0);
+        assert_eq!(angle((4, 2)), 90);
+        assert_eq!(angle((2, 4)), 180);
+        assert_eq!(angle((0, 2)), 270);
+        assert_eq!(angle((4, 0)), 45);
+    }
+
+    #[test]
+    fn walls_muffle_sounds() {
+        let open = map(&["_____"]);
+        let walled = map(&["__#__"]);
+
+        let heard = sound_position(&open, (0, 0), (4, 0)).unwrap();
+        let muffled = sound_position(&walled, (0, 0), (4, 0)).unwrap();
+        assert_eq!(
+            muffled.distance as u32,
+            heard.distance as u32 + OCCLUSION_PENALTY
+        );
+        assert_eq!(sound_position(&open, (0, 0), (0, 0)).unwrap().distance, 0);
+    }
+
+    #[test]
+    fn far_sounds_are_not_heard() {
+        let far = map(&[&"_".repeat(20)]);
+        let walled = map(&["_#####_"]);
+
+        assert_eq!(sound_position(&far, (0, 0), (19, 0)), None);
+        assert_eq!(sound_position(&walled, (0, 0), (6, 0)), None);
+    }
+
+    #[test]
+    fn loud_sounds_are_heard_from_anywhere() {
+        let far = map(&[&"_".repeat(20)]);
+        let walled = map(&["_#####_"]);
+        let barely = SoundPosition {
+            angle: 90,
+            distance: 255,
+        };
+
+        assert_eq!(loud_sound_position(&far, (0, 0), (19, 0)), barely);
+        assert_eq!(loud_sound_position(&walled, (0, 0), (6, 0)), barely);
+        assert_eq!(
+            Some(loud_sound_position(&far, (0, 0), (3, 0))),
+            sound_position(&far, (0, 0), (3, 0))
+        );
+    }
+
+    #[test]
+    fn lines_skip_their_ends() {
+        assert_eq!(line_between((0, 0), (3, 0)), [(1, 0), (2, 0)]);
+        assert_eq!(line_between((3, 3), (1, 1)), [(2, 2)]);
+        assert!(line_between((1, 1), (1, 1)).is_empty());
+        assert_eq!(line_between((0, 0), (2, 1)).len(), 1);
+    }
+
+    // Mono 16 bit wav
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for sample in samples {
+            data.push(*sample as u8);
+            data.push((*sample >> 8) as u8);
+        }
+        let fmt = [1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0];
+
+        let mut wav: Vec<u8> = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        push_u32(&mut wav, 4 + 8 + fmt.len() + 8 + data.len());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt ");
+        push_u32(&mut wav, fmt.len());
+        wav.extend_from_slice(&fmt);
+        wav.extend_from_slice(b"data");
+        push_u32(&mut wav, data.len());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[test]
+    fn variants_share_the_event() {
+        assert_eq!(event_key("resource_gone1.wav"), "resource_gone");
+        assert_eq!(event_key("resource_gone12.wav"), "resource_gone");
+        assert_eq!(event_key("monster_step.wav"), "monster_step");
+        assert_eq!(event_key("danger2"), "danger");
+    }
+
+    #[test]
+    fn pitched_wavs_change_speed() {
+        let original = wav(&[0, 1, 2, 3, 4, 5]);
+        assert_eq!(pitched_wav(&original, 1.0), Some(original.clone()));
+        assert_eq!(pitched_wav(&original, 2.0), Some(wav(&[0, 2, 4])));
+        assert_eq!(
+            pitched_wav(&wav(&[0, 1, 2]), 0.5),
+            Some(wav(&[0, 0, 1, 1, 2, 2]))
+        );
+    }
+
+    #[test]
+    fn unknown_wav_chunks_are_skipped() {
+        // An odd sized chunk, padded, between the format and the samples
+        let mut listed = wav(&[0, 1, 2, 3]);
+        let samples = listed.split_off(36);
+        listed.extend_from_slice(b"LIST");
+        push_u32(&mut listed, 3);
+        listed.extend_from_slice(b"abc\0");
+        listed.extend_from_slice(&samples);
+
+        assert_eq!(pitched_wav(&listed, 2.0), Some(wav(&[0, 2])));
+    }
+
+    #[test]
+    fn only_uncompressed_wavs_are_pitched() {
+        assert_eq!(pitched_wav(b"not a wav at all", 2.0), None);
+
+        let mut compressed = wav(&[0, 1, 2, 3]);
+        compressed[20] = 2;
+        assert_eq!(pitched_wav(&compressed, 2.0), None);
     }
 }
@@ -0,0 +1,44 @@
+/*
+ * Fixtures shared by the tests of the library modules.
+ * They panic on any failure, the way tests do.
+ */
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use rusqlite::Connection;
+
+/*
+ * Database in memory, made by the statements of `batch`
+ */
+pub fn memory_db(batch: &str) -> Connection {
+    let db_conn = Connection::open_in_memory().unwrap();
+    db_conn.execute_batch(batch).unwrap();
+    db_conn
+}
+
+/*
+ * Game database with only the `messages` table, which is all
+ * a new game reads from it besides the settings
+ */
+pub fn messages_db(messages: &[(&str, &str)]) -> Connection {
+    let db_conn =
+        memory_db("create table messages(situation TEXT, message TEXT);");
+    for &(situation, message) in messages.iter() {
+        db_conn
+            .execute(
+                "insert into messages(situation, message) values (?, ?);",
+                &[&situation, &message],
+            )
+            .unwrap();
+    }
+    db_conn
+}
+
+/*
+ * Directory of one test in one run, so tests running at the same
+ * time do not meet. It is not created.
+ */
+pub fn temp_dir(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("kobald-{}-{}", process::id(), name))
+}